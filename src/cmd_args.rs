@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{self, Deserialize, Serialize};
 use std::fmt::Debug;
 
@@ -7,16 +7,81 @@ use std::fmt::Debug;
 pub struct Args {
     #[command(subcommand)]
     pub cmds: Commands,
+
+    /// List the messages a command would change without changing anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Output txt file name that the dry run listing is written to instead of printing it
+    #[arg(long, global = true, value_name = "OUTPUT FILE", requires("dry_run"))]
+    pub dry_run_output: Option<String>,
+
+    /// Keep the label list in label_cache.json for this long (e.g. 30m, 12h, 1d), so runs within it
+    /// don't list the labels again. Labels are listed once per run when not given
+    #[arg(long, global = true, value_name = "INTERVAL", value_parser = crate::daemon::parse_interval)]
+    pub label_cache_ttl: Option<chrono::Duration>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Trashes email within specified label(s) or specified message(s) in authenticated email
     Trash(Trash),
+    /// Untrashes (restores) trashed email within specified label(s) or specified message(s) in authenticated email
+    Untrash(Untrash),
+    /// Permanently deletes email within specified label(s) or specified message(s) in authenticated email.
+    /// Deleted email skips the trash and cannot be recovered
+    Delete(Delete),
+    /// Adds and removes labels on email within specified label(s) or specified message(s) in authenticated email
+    Label(Label),
+    /// Archives (removes from the inbox) email within specified label(s) or specified message(s) in authenticated email
+    Archive(MsgAction),
+    /// Marks email within specified label(s) or specified message(s) in authenticated email as read
+    MarkRead(MsgAction),
+    /// Marks email within specified label(s) or specified message(s) in authenticated email as unread
+    MarkUnread(MsgAction),
+    /// Stars email within specified label(s) or specified message(s) in authenticated email
+    Star(MsgAction),
+    /// Unstars email within specified label(s) or specified message(s) in authenticated email
+    Unstar(MsgAction),
+    /// Reports email within specified label(s) or specified message(s) in authenticated email as spam,
+    /// moving it out of the inbox into spam
+    Spam(MsgAction),
+    /// Moves email within specified label(s) or specified message(s) in authenticated email out of spam
+    /// and back into the inbox
+    NotSpam(MsgAction),
+    /// Unsubscribes from the mailing lists of filtered messages through their List-Unsubscribe headers
+    Unsubscribe(Box<Unsubscribe>),
+    /// Trashes duplicate copies of email within specified label(s) or specified message(s) in authenticated email,
+    /// keeping the oldest copy
+    Dedupe(Dedupe),
+    /// Reports the senders and sender domains with the most filtered messages in authenticated email,
+    /// optionally letting you pick rows to trash or label
+    Senders(Box<Senders>),
+    /// Reports the totals of authenticated email and its largest messages with their attachments,
+    /// grouped by label and by sender, optionally trashing them
+    Storage(Storage),
+    /// Applies every retention rule within a TOML or JSON rules file to authenticated email
+    ApplyRules(ApplyRules),
+    /// Keeps running and applies the rules of each job within a TOML or JSON daemon config file on its schedule
+    Daemon(Daemon),
+    /// Records when messages within watched labels are read, so they can be trashed a while after being read
+    #[command(subcommand)]
+    ReadState(ReadStateOptions),
+    /// Undoes the trash, untrash and label changes of the most recent run, or of a chosen run, recorded in the journal
+    Undo(Undo),
     /// Sends an email to specified email address(es)
     Send(Box<Send>),
-    /// List all labels within authenticated email
-    Labels,
+    /// List all labels within authenticated email, or create, rename, delete, export, import or show the tree of labels
+    Labels(LabelsCmd),
+    /// Lists, creates or deletes the filters Gmail itself runs on incoming email
+    #[command(subcommand)]
+    GmailFilters(GmailFiltersOptions),
+    /// Shows, enables or disables the vacation auto-reply of authenticated email
+    #[command(subcommand)]
+    Vacation(VacationOptions),
+    /// Lists the send-as aliases of authenticated email, or sets their signatures, reply-to addresses or the default alias
+    #[command(subcommand)]
+    SendAs(SendAsOptions),
     /// Filters messages in authenticated email and outputs them in a txt file.
     /// See Google's "Refine searches in Gmail" for more info on email search query
     Filter(Box<FilterWithOutput>),
@@ -36,27 +101,511 @@ pub struct Trash {
         value_parser(1..11),
     )]
     pub threads_num: i64,
+
+    /// Act on whole conversations (gmail threads) that the selected messages belong to instead of single messages
+    #[arg(long)]
+    pub by_thread: bool,
+
+    /// Also trash starred, important and other protected messages (see protect.toml)
+    #[arg(long)]
+    pub include_protected: bool,
+
+    /// Only trash selected messages that were read longer ago than this (e.g. 12h, 1d), going by
+    /// the read times recorded for watched labels (see the read-state command). A message's read
+    /// time is when a sync first saw it read, so it's only as accurate as the time between syncs
+    #[arg(long, value_name = "DURATION", conflicts_with("by_thread"))]
+    pub read_more_than: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Untrash {
+    #[command(subcommand)]
+    pub untrash_opt: TrashOptions,
+
+    /// The number of threads desired by the user to untrash emails. Limited between 1 to 10 threads inclusive.
+    #[arg(
+        short,
+        long,
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser(1..11),
+    )]
+    pub threads_num: i64,
+}
+
+#[derive(Parser, Debug)]
+pub struct Delete {
+    #[command(subcommand)]
+    pub delete_opt: TrashOptions,
+
+    /// Permanently delete the matched messages without asking for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// The most messages allowed to be permanently deleted in one run
+    #[arg(short, long, value_name = "NUM", default_value_t = 1000)]
+    pub max_count: usize,
+
+    /// Allow permanently deleting more messages than the max count
+    #[arg(long)]
+    pub force: bool,
+
+    /// Also delete starred, important and other protected messages (see protect.toml)
+    #[arg(long)]
+    pub include_protected: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Label {
+    #[command(subcommand)]
+    pub label_opt: TrashOptions,
+
+    /// Label names to add to the messages
+    #[arg(
+        short,
+        long,
+        value_name = "LABEL_NAMES",
+        required_unless_present("remove")
+    )]
+    pub add: Vec<String>,
+
+    /// Label names to remove from the messages (e.g. INBOX to archive them)
+    #[arg(short, long, value_name = "LABEL_NAMES")]
+    pub remove: Vec<String>,
+
+    /// The number of threads desired by the user to label emails. Limited between 1 to 10 threads inclusive.
+    #[arg(
+        short,
+        long,
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser(1..11),
+    )]
+    pub threads_num: i64,
+
+    /// Act on whole conversations (gmail threads) that the selected messages belong to instead of single messages
+    #[arg(long)]
+    pub by_thread: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct MsgAction {
+    #[command(subcommand)]
+    pub action_opt: TrashOptions,
+
+    /// The number of threads desired by the user to update emails. Limited between 1 to 10 threads inclusive.
+    #[arg(
+        short,
+        long,
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser(1..11),
+    )]
+    pub threads_num: i64,
+
+    /// Act on whole conversations (gmail threads) that the selected messages belong to instead of single messages
+    #[arg(long)]
+    pub by_thread: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Unsubscribe {
+    #[clap(flatten)]
+    pub filter: Filter,
+
+    /// The email address mailto unsubscribes are sent from
+    #[arg(long, value_name = "EMAIL_ADDR", requires("relay"))]
+    pub send_from: Option<String>,
+
+    /// The username to the host website mailto unsubscribes are sent through. Credentials for user is stored in credentials.json
+    #[arg(long, value_name = "USERNAME", requires("password"))]
+    pub username: Option<String>,
+
+    /// The password to the host website mailto unsubscribes are sent through. Credentials for pass is stored in credentials.json
+    #[arg(long, value_name = "PASSWORD", requires("username"))]
+    pub password: Option<String>,
+
+    /// The host site that mailto unsubscribes are sent through
+    #[arg(long, value_name = "HOST SITE", requires("send_from"))]
+    pub relay: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Dedupe {
+    #[command(subcommand)]
+    pub dedupe_opt: TrashOptions,
+
+    /// Group messages by a hash of their main headers (From, To, Cc, Subject, Date) and body
+    /// instead of by their Message-ID header, catching copies whose Message-ID changed or is missing
+    #[arg(long)]
+    pub content_hash: bool,
+
+    /// The number of threads desired by the user to trash duplicates. Limited between 1 to 10 threads inclusive.
+    #[arg(
+        short,
+        long,
+        value_name = "NUM",
+        default_value_t = 1,
+        value_parser(1..11),
+    )]
+    pub threads_num: i64,
+    /// Also trash starred, important and other protected duplicates (see protect.toml)
+    #[arg(long)]
+    pub include_protected: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Senders {
+    #[clap(flatten)]
+    pub filter: Filter,
+
+    /// The number of senders and sender domains listed in each table
+    #[arg(short = 'n', long, value_name = "NUM", default_value_t = 20)]
+    pub top: usize,
+
+    /// After the tables are printed, pick rows whose messages get trashed or labeled
+    #[arg(short, long)]
+    pub interactive: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Storage {
+    /// The number of largest messages listed
+    #[arg(short = 'n', long, value_name = "NUM", default_value_t = 20)]
+    pub top: usize,
+
+    /// Only messages larger than this many bytes are scanned
+    #[arg(short, long, value_name = "BYTES", default_value_t = 1048576)]
+    pub larger: usize,
+
+    /// Trash the listed messages after reporting them
+    #[arg(long)]
+    pub trash: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct LabelsCmd {
+    #[command(subcommand)]
+    pub labels_opt: Option<LabelsOptions>,
+
+    /// Format the label listing is printed in when no subcommand is given
+    #[arg(long, value_enum, default_value_t = LabelsFormat::Table)]
+    pub format: LabelsFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum LabelsFormat {
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// A JSON array with an object per label
+    Json,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LabelsOptions {
+    /// Creates a label, along with any missing parent labels of a `/`-nested name (e.g. Work/Projects)
+    Create(LabelName),
+    /// Renames a label, moving every label nested under it along with it
+    Rename(LabelRename),
+    /// Deletes a label. Messages with the label are kept
+    Delete(LabelDelete),
+    /// Shows user labels as a tree nested by the `/` in their names
+    Tree,
+    /// Writes every user label's name, color and visibility settings to a JSON file
+    Export(LabelsFile),
+    /// Creates the labels of an exported JSON file that are missing from the authenticated email
+    Import(LabelsFile),
+}
+
+#[derive(Parser, Debug)]
+pub struct LabelName {
+    /// Label name within user's email
+    #[arg(short, long, value_name = "LABEL_NAME")]
+    pub name: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct LabelRename {
+    /// Current label name within user's email
+    #[arg(short, long, value_name = "LABEL_NAME")]
+    pub from: String,
+
+    /// New label name; nesting it under another label with `/` moves the label there
+    #[arg(short, long, value_name = "LABEL_NAME")]
+    pub to: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct LabelDelete {
+    /// Label name within user's email
+    #[arg(short, long, value_name = "LABEL_NAME")]
+    pub name: String,
+
+    /// Also delete every label nested under it
+    #[arg(short, long)]
+    pub recursive: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct LabelsFile {
+    /// JSON file the labels are exported to or imported from
+    #[arg(short, long, value_name = "JSON FILE")]
+    pub file: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GmailFiltersOptions {
+    /// Lists every Gmail filter with its criteria and actions
+    List,
+    /// Creates a Gmail filter from filter options, a query text file or a filter json file.
+    /// Fields with a single value become Gmail's own criteria and the rest goes into the criteria query
+    Create(Box<GmailFilterCreate>),
+    /// Deletes Gmail filters by id
+    Delete(GmailFilterIds),
+    /// Writes every Gmail filter to a mailFilters.xml file (as exported by the Gmail web UI),
+    /// or to a filter json file when the file has a .json extension
+    Export(GmailFiltersFile),
+    /// Creates the filters of a mailFilters.xml file, or of a filter json file when the file has a
+    /// .json extension, that aren't configured in the authenticated email yet
+    Import(GmailFiltersFile),
+}
+
+#[derive(Parser, Debug)]
+pub struct GmailFilterCreate {
+    #[clap(flatten)]
+    pub filter: Filter,
+
+    #[clap(flatten)]
+    pub actions: GmailFilterActions,
+}
+
+#[derive(Parser, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GmailFilterActions {
+    /// Label names to add to matching email
+    #[arg(
+        long,
+        value_name = "LABEL_NAMES",
+        required_unless_present_any(["remove", "forward", "trash"])
+    )]
+    #[serde(default)]
+    pub add: Vec<String>,
+
+    /// Label names to remove from matching email (e.g. INBOX to skip the inbox)
+    #[arg(long, value_name = "LABEL_NAMES")]
+    #[serde(default)]
+    pub remove: Vec<String>,
+
+    /// Email address to forward matching email to. It has to be a verified forwarding address
+    #[arg(long, value_name = "EMAIL")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward: Option<String>,
+
+    /// Send matching email to the trash
+    #[arg(long)]
+    #[serde(default)]
+    pub trash: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct GmailFiltersFile {
+    /// mailFilters.xml file, or filter json file with a .json extension, the filters are exported to or imported from
+    #[arg(short, long, value_name = "FILTERS FILE")]
+    pub file: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct GmailFilterIds {
+    /// Ids of Gmail filters, as shown by gmail-filters list
+    #[arg(short, long, value_name = "FILTER_IDS", required = true)]
+    pub ids: Vec<String>,
 }
 
 #[derive(Subcommand, Debug)]
+pub enum VacationOptions {
+    /// Shows the vacation auto-reply settings
+    Show,
+    /// Turns the vacation auto-reply on, replacing its previous subject, body, times and restrictions
+    Enable(VacationEnable),
+    /// Turns the vacation auto-reply off, keeping its settings for the next time it's turned on
+    Disable,
+}
+
+#[derive(Parser, Debug)]
+pub struct VacationEnable {
+    /// Text put before the subject of auto-replies
+    #[arg(short, long, value_name = "SUBJECT", required_unless_present_any(["body", "body_file"]))]
+    pub subject: Option<String>,
+
+    /// Body of auto-replies
+    #[arg(short, long, value_name = "BODY", conflicts_with("body_file"))]
+    pub body: Option<String>,
+
+    /// File holding the body of auto-replies
+    #[arg(long, value_name = "BODY FILE")]
+    pub body_file: Option<String>,
+
+    /// The body is HTML rather than plain text
+    #[arg(long)]
+    pub html: bool,
+
+    /// Only reply to email received after this time: RFC 3339 (2024-07-01T09:00:00-04:00),
+    /// or local time as "2024-07-01 09:00" or 2024-07-01 (midnight)
+    #[arg(long, value_name = "TIME", value_parser = crate::vacation::parse_time)]
+    pub start: Option<i64>,
+
+    /// Only reply to email received before this time, in the same formats as --start
+    #[arg(long, value_name = "TIME", value_parser = crate::vacation::parse_time)]
+    pub end: Option<i64>,
+
+    /// Only reply to senders in the contacts list
+    #[arg(long)]
+    pub contacts_only: bool,
+
+    /// Only reply to senders within the same domain (Google Workspace accounts only)
+    #[arg(long)]
+    pub domain_only: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SendAsOptions {
+    /// Lists every send-as alias with its display name, reply-to address and signature
+    List,
+    /// Sets the signature of aliases from an HTML file, or a Markdown file with a .md or .markdown extension
+    Signature(SendAsSignature),
+    /// Makes an alias the default "From:" address
+    Default(SendAsDefault),
+    /// Sets or clears the reply-to address of aliases
+    ReplyTo(SendAsReplyTo),
+}
+
+#[derive(Parser, Debug)]
+pub struct SendAsSignature {
+    /// HTML file, or Markdown file with a .md or .markdown extension, holding the signature
+    #[arg(short, long, value_name = "SIGNATURE FILE")]
+    pub file: String,
+
+    /// Email addresses of the aliases to update
+    #[arg(
+        short,
+        long,
+        value_name = "ALIASES",
+        required_unless_present("all_aliases"),
+        conflicts_with("all_aliases")
+    )]
+    pub email: Vec<String>,
+
+    /// Update every alias
+    #[arg(long)]
+    pub all_aliases: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct SendAsDefault {
+    /// Email address of the alias
+    #[arg(short, long, value_name = "ALIAS")]
+    pub email: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct SendAsReplyTo {
+    /// Reply-To address put on email sent from the aliases
+    #[arg(
+        short,
+        long,
+        value_name = "EMAIL",
+        required_unless_present("clear"),
+        conflicts_with("clear")
+    )]
+    pub address: Option<String>,
+
+    /// Remove the reply-to address instead, so no Reply-To header is added
+    #[arg(long)]
+    pub clear: bool,
+
+    /// Email addresses of the aliases to update
+    #[arg(
+        short,
+        long,
+        value_name = "ALIASES",
+        required_unless_present("all_aliases"),
+        conflicts_with("all_aliases")
+    )]
+    pub email: Vec<String>,
+
+    /// Update every alias
+    #[arg(long)]
+    pub all_aliases: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ApplyRules {
+    /// TOML or JSON file containing the rules, picked by the file extension
+    #[arg(short, long, value_name = "RULES FILE")]
+    pub file: String,
+
+    /// Permanently delete messages matched by delete rules without asking for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReadStateOptions {
+    /// Starts recording when messages within the given label names are read
+    Watch(Labels),
+    /// Stops recording when messages within the given label names are read
+    Unwatch(Labels),
+    /// Records every message within a watched label that was read since the last sync
+    Sync,
+}
+
+#[derive(Parser, Debug)]
+pub struct Undo {
+    /// Id of the run to undo; the most recent run in the journal is undone if none is given
+    #[arg(value_name = "RUN_ID", conflicts_with("list"))]
+    pub run_id: Option<String>,
+
+    /// List every run recorded in the journal instead of undoing one
+    #[arg(short, long)]
+    pub list: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Daemon {
+    /// TOML or JSON file containing the scheduled jobs, picked by the file extension
+    #[arg(short, long, value_name = "CONFIG FILE")]
+    pub file: String,
+
+    /// Log file that every run of a job is appended to
+    #[arg(short, long, value_name = "LOG FILE")]
+    pub log: Option<String>,
+
+    /// Permanently delete messages matched by delete rules without asking for confirmation.
+    /// Required when any job has a delete rule, since the daemon can't ask
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Subcommand, Debug, Clone)]
 pub enum TrashOptions {
-    /// Trash all messages by message ids
+    /// Select all messages by message ids
     ByMsgIds(MsgIds),
-    /// Trash all messages by label names
+    /// Select all messages by label names
     ByLabels(Labels),
-    /// Trash all messages by filter query.
+    /// Select all messages by filter query.
     /// See Google's "Refine searches in Gmail" for more info on email search query
     ByFilter(Box<Filter>),
 }
 
-#[derive(Parser, Debug, Serialize, Deserialize)]
+#[derive(Parser, Debug, Serialize, Deserialize, Clone)]
 pub struct MsgIds {
     /// Message IDs of emails
     #[arg(short, long, value_name = "MESSAGE_ID")]
     pub msg_ids: Vec<String>,
 }
 
-#[derive(Parser, Debug, Serialize, Deserialize)]
+#[derive(Parser, Debug, Serialize, Deserialize, Clone)]
 pub struct Labels {
     /// Label names within user's email
     #[arg(short, long, value_name = "LABEL_NAMES")]
@@ -205,7 +754,7 @@ pub enum FilterCmd {
 
 /// Filters messages in authenticated email for trashing purposes
 /// See Google's "Refine searches in Gmail" for more info on email search query
-#[derive(Parser, Debug, Clone, Serialize, Deserialize)]
+#[derive(Parser, Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Filter {
     /// Messages that contain these specific word(s) or phrase(s)
@@ -0,0 +1,526 @@
+extern crate google_gmail1 as gmail1;
+
+use chrono::{DateTime, Datelike, Duration, Local, Timelike};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::journal;
+use crate::label_registry;
+use crate::read_state;
+use crate::rules::{self, Rule, RuleAction};
+
+/// A daemon config file holding every scheduled job
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub jobs: Vec<Job>,
+}
+
+/// A scheduled job: the rules it applies and either a fixed interval or a cron expression
+/// saying when to apply them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    /// Name of the job shown in the log
+    pub name: String,
+
+    /// Fixed interval between runs (e.g. 30m, 12h, 1d); the job also runs once on startup
+    #[serde(default)]
+    pub every: Option<String>,
+
+    /// Cron expression (minute hour day-of-month month day-of-week) in local time
+    #[serde(default)]
+    pub cron: Option<String>,
+
+    /// Rules file to apply on each run
+    #[serde(default)]
+    pub rules_file: Option<String>,
+
+    /// Rules to apply on each run, after the ones in the rules file
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+/// When a job runs
+enum Schedule {
+    Every(Duration),
+    Cron(CronSchedule),
+}
+
+/// A parsed cron expression; each field holds every value it matches
+struct CronSchedule {
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days_of_month: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    days_of_week: BTreeSet<u32>,
+    // Cron matches either day field when both are restricted, so this needs to be known
+    days_of_month_any: bool,
+    days_of_week_any: bool,
+}
+
+/// A job ready to run: its rules loaded and its schedule parsed
+struct ScheduledJob {
+    name: String,
+    rules: Vec<Rule>,
+    schedule: Schedule,
+    next_run: DateTime<Local>,
+}
+
+/// Parses an interval like 45s, 30m, 12h or 1d
+pub fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let interval = interval.trim();
+    let unit_index = interval.find(|c: char| !c.is_ascii_digit()).ok_or(format!(
+        "Interval {} is missing a unit (s, m, h, d)",
+        interval
+    ))?;
+    let (amount, unit) = interval.split_at(unit_index);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("Interval {} doesn't start with a number", interval))?;
+
+    let duration = match unit {
+        "s" => Duration::seconds(amount),
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        _ => {
+            return Err(format!(
+                "Interval {} has an unknown unit {}",
+                interval, unit
+            ))
+        }
+    };
+
+    if duration <= Duration::zero() {
+        return Err(format!("Interval {} has to be longer than 0", interval));
+    }
+
+    Ok(duration)
+}
+
+/// Parses one cron field (e.g. *, */15, 1-5, 0,30) into every value it matches between min and max
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>, String> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or(format!("Cron field {} has an invalid step", field))?,
+            ),
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                start
+                    .parse::<u32>()
+                    .map_err(|_| format!("Cron field {} has an invalid range", field))?,
+                end.parse::<u32>()
+                    .map_err(|_| format!("Cron field {} has an invalid range", field))?,
+            )
+        } else {
+            let value = range
+                .parse::<u32>()
+                .map_err(|_| format!("Cron field {} has an invalid value", field))?;
+            // A single value with a step (e.g. 5/15) runs from that value to the max
+            if step > 1 {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!(
+                "Cron field {} has to be between {} and {}",
+                field, min, max
+            ));
+        }
+
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    Ok(values)
+}
+
+/// Parses a five field cron expression (minute hour day-of-month month day-of-week)
+fn parse_cron(expression: &str) -> Result<CronSchedule, String> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!(
+            "Cron expression {} needs 5 fields: minute hour day-of-month month day-of-week",
+            expression
+        ));
+    }
+
+    // Both 0 and 7 mean Sunday in the day of week field
+    let days_of_week = parse_cron_field(fields[4], 0, 7)?
+        .into_iter()
+        .map(|day| day % 7)
+        .collect();
+
+    Ok(CronSchedule {
+        minutes: parse_cron_field(fields[0], 0, 59)?,
+        hours: parse_cron_field(fields[1], 0, 23)?,
+        days_of_month: parse_cron_field(fields[2], 1, 31)?,
+        months: parse_cron_field(fields[3], 1, 12)?,
+        days_of_week,
+        days_of_month_any: fields[2] == "*",
+        days_of_week_any: fields[4] == "*",
+    })
+}
+
+impl CronSchedule {
+    /// Checks whether the cron expression matches the given minute
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let day_of_month = self.days_of_month.contains(&time.day());
+        let day_of_week = self
+            .days_of_week
+            .contains(&time.weekday().num_days_from_sunday());
+        let day = match (self.days_of_month_any, self.days_of_week_any) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+
+        day && self.minutes.contains(&time.minute())
+            && self.hours.contains(&time.hour())
+            && self.months.contains(&time.month())
+    }
+
+    /// Finds the first minute after the given time that the cron expression matches,
+    /// looking at most a little over four years ahead (enough to reach any Feb 29)
+    fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut candidate = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let last_candidate = time + Duration::days(4 * 366);
+
+        while candidate <= last_candidate {
+            if self.matches(&candidate) {
+                return Some(candidate);
+            }
+            candidate += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+impl Schedule {
+    /// The next time a job on this schedule should run after the given time
+    fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Every(interval) => Some(time + *interval),
+            Schedule::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+/// Loads the rules and parses the schedule of every job in the daemon config file
+fn load_jobs(config_file: &str) -> Result<Vec<ScheduledJob>, Box<dyn std::error::Error>> {
+    let config: DaemonConfig = rules::read_toml_or_json(config_file)?;
+    let now = Local::now();
+    let mut jobs = Vec::with_capacity(config.jobs.len());
+
+    for job in config.jobs {
+        let mut job_rules = match &job.rules_file {
+            Some(rules_file) => rules::load_rules(rules_file)?,
+            None => Vec::new(),
+        };
+        rules::validate_rules(&job.rules)?;
+        job_rules.extend(job.rules);
+
+        if job_rules.is_empty() {
+            return Err(format!("Job {} has no rules to apply", job.name).into());
+        }
+
+        let (schedule, next_run) = match (&job.every, &job.cron) {
+            (Some(every), None) => (Schedule::Every(parse_interval(every)?), now),
+            (None, Some(cron)) => {
+                let cron = parse_cron(cron)?;
+                let next_run = cron
+                    .next_after(now)
+                    .ok_or(format!("Job {} never runs on its cron schedule", job.name))?;
+                (Schedule::Cron(cron), next_run)
+            }
+            _ => return Err(format!("Job {} needs exactly one of every or cron", job.name).into()),
+        };
+
+        jobs.push(ScheduledJob {
+            name: job.name,
+            rules: job_rules,
+            schedule,
+            next_run,
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Prints a line to stdout with a timestamp, also appending it to the log file if there is one
+fn log(log_file: &Option<String>, line: &str) {
+    let line = format!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), line);
+    println!("{}", line);
+
+    if let Some(log_file) = log_file {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = result {
+            println!(
+                "Couldn't write to log file {}.\nError Received: {}",
+                log_file, e
+            );
+        }
+    }
+}
+
+/// Waits for SIGTERM (or Ctrl-C) so the daemon can shut down between runs
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM");
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Keeps the authenticated session alive and applies the rules of each job in the daemon config
+/// file whenever its schedule comes up, logging each run. A run that has started is always
+/// finished; SIGTERM or Ctrl-C stops the daemon while it waits for the next run
+pub async fn run_daemon(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    config_file: &str,
+    log_file: &Option<String>,
+    yes: bool,
+    dry_run: bool,
+    dry_run_output: &Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut jobs = load_jobs(config_file)?;
+    if jobs.is_empty() {
+        return Err("The daemon config has no jobs".into());
+    }
+
+    // Nobody is around to answer a delete confirmation on stdin, and waiting on one would
+    // stall every later job
+    if !yes && !dry_run {
+        let delete_job = jobs.iter().find(|job| {
+            job.rules
+                .iter()
+                .any(|rule| matches!(rule.action, RuleAction::Delete))
+        });
+        if let Some(delete_job) = delete_job {
+            return Err(format!(
+                "Job {} has a delete rule, which the daemon can't confirm on stdin. Pass --yes to delete without confirmation",
+                delete_job.name
+            )
+            .into());
+        }
+    }
+
+    log(
+        log_file,
+        &format!("Daemon started with {} jobs", jobs.len()),
+    );
+    for job in &jobs {
+        log(
+            log_file,
+            &format!(
+                "Job {} first runs at {}",
+                job.name,
+                job.next_run.format("%Y-%m-%d %H:%M")
+            ),
+        );
+    }
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        // Jobs that can never run again are removed from the vec, so the soonest one is always due next
+        let Some(index) = (0..jobs.len()).min_by_key(|index| jobs[*index].next_run) else {
+            log(log_file, "No jobs left to run");
+            break;
+        };
+        let next_job = &mut jobs[index];
+
+        let wait = (next_job.next_run - Local::now())
+            .to_std()
+            .unwrap_or(std::time::Duration::ZERO);
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = &mut shutdown => {
+                log(log_file, "Received shutdown signal, stopping daemon");
+                break;
+            }
+        }
+
+        log(log_file, &format!("Running job {}", next_job.name));
+        journal::start_run();
+        label_registry::start_session();
+        // Read times are only as accurate as how often the state store is synced, so every
+        // run keeps it current for trash --read-more-than
+        if read_state::is_watching() {
+            if let Err(e) = read_state::sync(hub).await {
+                log(
+                    log_file,
+                    &format!("Could not sync read state.\nError Received: {}", e),
+                );
+            }
+        }
+        let summaries =
+            rules::apply_rules(hub, &next_job.rules, yes, dry_run, dry_run_output).await;
+        for summary in summaries {
+            log(
+                log_file,
+                &format!("Job {} rule {}", next_job.name, summary.describe()),
+            );
+        }
+
+        match next_job.schedule.next_after(Local::now()) {
+            Some(next_run) => {
+                next_job.next_run = next_run;
+                log(
+                    log_file,
+                    &format!(
+                        "Job {} next runs at {}",
+                        next_job.name,
+                        next_run.format("%Y-%m-%d %H:%M")
+                    ),
+                );
+            }
+            None => {
+                log(log_file, &format!("Job {} won't run again", next_job.name));
+                jobs.remove(index);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .single()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("45s"), Ok(Duration::seconds(45)));
+        assert_eq!(parse_interval(" 30m "), Ok(Duration::minutes(30)));
+        assert_eq!(parse_interval("12h"), Ok(Duration::hours(12)));
+        assert_eq!(parse_interval("1d"), Ok(Duration::days(1)));
+    }
+
+    #[test]
+    fn rejects_bad_intervals() {
+        for interval in ["30", "m", "0m", "5w", "1.5h"] {
+            assert!(parse_interval(interval).is_err(), "{}", interval);
+        }
+    }
+
+    #[test]
+    fn parses_cron_fields() {
+        let cron = parse_cron("*/15 9-17 1,15 * 0").unwrap();
+        assert_eq!(cron.minutes, BTreeSet::from([0, 15, 30, 45]));
+        assert_eq!(cron.hours, (9..=17).collect());
+        assert_eq!(cron.days_of_month, BTreeSet::from([1, 15]));
+        assert_eq!(cron.months, (1..=12).collect());
+        assert_eq!(cron.days_of_week, BTreeSet::from([0]));
+        assert!(!cron.days_of_month_any);
+        assert!(!cron.days_of_week_any);
+
+        // 7 is Sunday too, and a single value with a step runs to the max
+        let cron = parse_cron("5/20 0 * * 7").unwrap();
+        assert_eq!(cron.minutes, BTreeSet::from([5, 25, 45]));
+        assert_eq!(cron.days_of_week, BTreeSet::from([0]));
+    }
+
+    #[test]
+    fn rejects_bad_cron_expressions() {
+        for expression in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(parse_cron(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn finds_the_next_cron_minute() {
+        let cron = parse_cron("30 9 * * *").unwrap();
+        assert_eq!(
+            cron.next_after(local(2024, 1, 10, 8, 0)),
+            Some(local(2024, 1, 10, 9, 30))
+        );
+        // The current minute itself doesn't count
+        assert_eq!(
+            cron.next_after(local(2024, 1, 10, 9, 30)),
+            Some(local(2024, 1, 11, 9, 30))
+        );
+    }
+
+    #[test]
+    fn cron_matches_either_restricted_day_field() {
+        // The 15th of the month or any Monday; 2024-01-08 is a Monday
+        let cron = parse_cron("0 12 15 * 1").unwrap();
+        assert_eq!(
+            cron.next_after(local(2024, 1, 2, 0, 0)),
+            Some(local(2024, 1, 8, 12, 0))
+        );
+        assert_eq!(
+            cron.next_after(local(2024, 1, 13, 0, 0)),
+            Some(local(2024, 1, 15, 12, 0))
+        );
+
+        // With one day field left as *, only the other one counts
+        let cron = parse_cron("0 12 * * 1").unwrap();
+        assert_eq!(
+            cron.next_after(local(2024, 1, 9, 0, 0)),
+            Some(local(2024, 1, 15, 12, 0))
+        );
+    }
+
+    #[test]
+    fn cron_reaches_leap_days() {
+        let cron = parse_cron("0 0 29 2 *").unwrap();
+        assert_eq!(
+            cron.next_after(local(2024, 3, 1, 0, 0)),
+            Some(local(2028, 2, 29, 0, 0))
+        );
+    }
+}
@@ -0,0 +1,149 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::api::{Message, MessagePart};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::TrashOptions;
+use crate::mail_service as mail;
+
+/// The headers that go into a message's content hash along with its body
+const HASHED_HEADERS: [&str; 5] = ["From", "To", "Cc", "Subject", "Date"];
+
+/// Messages sharing the same Message-ID header (or content hash): the oldest copy that is kept
+/// and the duplicates to trash
+pub struct DuplicateGroup {
+    pub key: String,
+    pub keep: String,
+    pub duplicates: Vec<String>,
+}
+
+/// Feeds the body data, attachment names and sizes of a message part and every part nested in
+/// it into the hasher
+fn hash_part(part: &MessagePart, hasher: &mut DefaultHasher) {
+    part.filename.hash(hasher);
+    if let Some(body) = &part.body {
+        body.data.hash(hasher);
+        // Attachment data has to be fetched on its own, so only its size is hashed
+        if body.attachment_id.is_some() {
+            body.size.hash(hasher);
+        }
+    }
+    for part in part.parts.iter().flatten() {
+        hash_part(part, hasher);
+    }
+}
+
+/// Hashes a full message's main headers and body into a hex string
+fn content_hash(msg: &Message) -> Option<String> {
+    let payload = msg.payload.as_ref()?;
+    let mut hasher = DefaultHasher::new();
+    for header in HASHED_HEADERS {
+        mail::get_header(msg, header).hash(&mut hasher);
+    }
+    hash_part(payload, &mut hasher);
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+/// Fetches what a message is grouped by: its Message-ID header, or its content hash when
+/// by_content is set. Returns the key along with when the message arrived
+async fn dedupe_key(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id: &str,
+    by_content: bool,
+) -> Result<Option<(String, i64)>, Box<dyn std::error::Error>> {
+    let msg = if by_content {
+        mail::get_message(hub, msg_id).await?
+    } else {
+        mail::get_message_metadata(hub, msg_id, &["Message-ID"]).await?
+    };
+
+    let key = if by_content {
+        content_hash(&msg).map(|hash| format!("content {}", hash))
+    } else {
+        mail::get_header(&msg, "Message-ID").map(|msg_id_header| msg_id_header.trim().to_string())
+    };
+    Ok(key.map(|key| (key, msg.internal_date.unwrap_or(i64::MAX))))
+}
+
+/// Groups the selected messages by Message-ID header (or by content hash when by_content is set)
+/// and returns every group with more than one message, keeping the oldest copy of each
+pub async fn find_duplicate_groups(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    dedupe_opt: TrashOptions,
+    by_content: bool,
+) -> Vec<DuplicateGroup> {
+    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    mail::add_msg_ids_from_opt(hub, dedupe_opt, &[], msg_id_bts.clone()).await;
+    let msg_ids: Vec<String> = msg_id_bts.lock().await.iter().flatten().cloned().collect();
+
+    // Each key maps to its messages' (arrival time, id), so sorting puts the oldest first
+    let mut groups: BTreeMap<String, Vec<(i64, String)>> = BTreeMap::new();
+    let mut no_key: usize = 0;
+    for msg_id in msg_ids {
+        match dedupe_key(hub, &msg_id, by_content).await {
+            Ok(Some((key, internal_date))) => {
+                groups.entry(key).or_default().push((internal_date, msg_id))
+            }
+            Ok(None) => no_key += 1,
+            Err(e) => println!(
+                "Could not read message with id {}.\nError Received: {}",
+                msg_id, e
+            ),
+        }
+    }
+    if no_key > 0 {
+        println!(
+            "Skipped {} messages with no Message-ID header (try --content-hash)",
+            no_key
+        );
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_key, msgs)| msgs.len() > 1)
+        .map(|(key, mut msgs)| {
+            msgs.sort();
+            let mut msg_ids = msgs.into_iter().map(|(_internal_date, msg_id)| msg_id);
+            DuplicateGroup {
+                key,
+                keep: msg_ids.next().unwrap_or_default(),
+                duplicates: msg_ids.collect(),
+            }
+        })
+        .collect()
+}
+
+/// Lists each duplicate group: the copy that is kept followed by the duplicates that would be
+/// trashed, either to stdout or appended to the output txt file if one is given
+pub async fn preview_groups(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    groups: &[DuplicateGroup],
+    output_file: &Option<String>,
+) {
+    for group in groups {
+        let heading = format!(
+            "Duplicate group {} ({} duplicates)\nKeeping:\n",
+            group.key,
+            group.duplicates.len()
+        );
+        match output_file {
+            Some(output_file) => mail::append_to_output(output_file, &heading),
+            None => print!("{}", heading),
+        }
+        mail::preview_msg_ids(hub, std::slice::from_ref(&group.keep), output_file).await;
+
+        match output_file {
+            Some(output_file) => mail::append_to_output(output_file, "Trashing:\n"),
+            None => println!("Trashing:"),
+        }
+        mail::preview_msg_ids(hub, &group.duplicates, output_file).await;
+    }
+}
@@ -0,0 +1,1124 @@
+extern crate google_gmail1 as gmail1;
+
+use chrono::Utc;
+use gmail1::api::{Filter as GmailFilter, FilterAction, FilterCriteria};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::cmd_args::{Filter, GmailFilterActions, GmailFilterCreate};
+use crate::label_registry;
+use crate::labels;
+use crate::mail_service as mail;
+
+/// Gmail's mailFilters.xml boolean properties, the label each one adds (true) or removes (false)
+const FLAG_PROPERTIES: [(&str, &str, bool); 7] = [
+    ("shouldArchive", "INBOX", false),
+    ("shouldMarkAsRead", "UNREAD", false),
+    ("shouldNeverSpam", "SPAM", false),
+    ("shouldNeverMarkAsImportant", "IMPORTANT", false),
+    ("shouldStar", "STARRED", true),
+    ("shouldTrash", "TRASH", true),
+    ("shouldAlwaysMarkAsImportant", "IMPORTANT", true),
+];
+
+/// Gmail's mailFilters.xml smart labels and the category label each one stands for
+const SMART_LABELS: [(&str, &str); 5] = [
+    ("^smartlabel_personal", "CATEGORY_PERSONAL"),
+    ("^smartlabel_social", "CATEGORY_SOCIAL"),
+    ("^smartlabel_promo", "CATEGORY_PROMOTIONS"),
+    ("^smartlabel_notification", "CATEGORY_UPDATES"),
+    ("^smartlabel_group", "CATEGORY_FORUMS"),
+];
+
+/// Gmail's mailFilters.xml size units and how many bytes each one is
+const SIZE_UNITS: [(&str, i64); 3] = [("s_sb", 1), ("s_skb", 1024), ("s_smb", 1024 * 1024)];
+
+/// The property names and values of a mailFilters.xml entry
+type Properties = Vec<(String, String)>;
+
+/// A Gmail filter in a filter json file: the same filter options as a filter json file used by
+/// the filter command, along with the actions to take
+#[derive(Serialize, Deserialize)]
+struct FilterEntry {
+    filter: Filter,
+    #[serde(flatten)]
+    actions: GmailFilterActions,
+}
+
+/// Lists every Gmail filter of the authenticated email
+pub async fn list_filters(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<Vec<GmailFilter>, Box<dyn std::error::Error>> {
+    let (_res, filters_list) = hub
+        .users()
+        .settings_filters_list("me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await?;
+    Ok(filters_list.filter.unwrap_or_default())
+}
+
+/// Takes a field out of the filter when it holds exactly one value, leaving it in place otherwise
+/// since several values of a field are all required to match, which Gmail's criteria fields can't express
+fn take_single(values: &mut Option<Vec<String>>) -> Option<String> {
+    match values.as_deref() {
+        Some([value]) => {
+            let value = value.clone();
+            *values = None;
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+/// Turns a filter's options into Gmail filter criteria. Fields with a single value become Gmail's own
+/// from, to, subject and size criteria, words to leave out become the negated query, and every other
+/// field goes into the criteria query in Gmail search syntax
+async fn criteria_from_options(
+    mut filter: Filter,
+) -> Result<FilterCriteria, Box<dyn std::error::Error>> {
+    let mut criteria = FilterCriteria {
+        from: take_single(&mut filter.from),
+        to: take_single(&mut filter.to),
+        subject: take_single(&mut filter.subject),
+        ..Default::default()
+    };
+
+    if let Some(remove_words) = filter.remove_words.take() {
+        criteria.negated_query = Some(remove_words.join(" "));
+    }
+
+    if let Some(has) = filter.has.as_mut() {
+        if let Some(index) = has.iter().position(|has| has == "attachment") {
+            has.remove(index);
+            criteria.has_attachment = Some(true);
+        }
+    }
+
+    // Gmail's size criterion holds a single comparison, so a second one stays in the query
+    if let Some(larger) = filter.larger.and_then(|larger| i32::try_from(larger).ok()) {
+        criteria.size = Some(larger);
+        criteria.size_comparison = Some("larger".to_string());
+        filter.larger = None;
+    } else if let Some(smaller) = filter
+        .smaller
+        .and_then(|smaller| i32::try_from(smaller).ok())
+    {
+        criteria.size = Some(smaller);
+        criteria.size_comparison = Some("smaller".to_string());
+        filter.smaller = None;
+    }
+
+    let query = mail::query_parse(filter).await?;
+    let query = query.trim();
+    if !query.is_empty() {
+        criteria.query = Some(query.to_string());
+    }
+    Ok(criteria)
+}
+
+/// Turns a filter into Gmail filter criteria, whether it's given in a text file (used as the
+/// criteria query), a json file or through the filter options
+pub async fn criteria_from_filter(
+    filter: &Filter,
+) -> Result<FilterCriteria, Box<dyn std::error::Error>> {
+    if let Some(text_file) = &filter.txt {
+        let query = fs::read_to_string(text_file)
+            .map_err(|e| format!("Unable to read file {}.\nError Received: {}", text_file, e))?;
+        return Ok(FilterCriteria {
+            query: Some(query.trim().to_string()),
+            ..Default::default()
+        });
+    }
+    if let Some(json_file) = &filter.json {
+        let filter_string = fs::read_to_string(json_file)
+            .map_err(|e| format!("Unable to read file {}.\nError Received: {}", json_file, e))?;
+        let filter: Filter = serde_json::from_str(&filter_string)
+            .map_err(|e| format!("JSON was not well-formatted.\nError Received: {}", e))?;
+        return criteria_from_options(filter).await;
+    }
+    criteria_from_options(filter.clone()).await
+}
+
+/// Describes filter criteria in Gmail search syntax
+pub fn describe_criteria(criteria: &FilterCriteria) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if let Some(from) = &criteria.from {
+        parts.push(format!("from:({})", from));
+    }
+    if let Some(to) = &criteria.to {
+        parts.push(format!("to:({})", to));
+    }
+    if let Some(subject) = &criteria.subject {
+        parts.push(format!("subject:({})", subject));
+    }
+    if let Some(query) = &criteria.query {
+        parts.push(query.clone());
+    }
+    if let Some(negated_query) = &criteria.negated_query {
+        parts.push(format!("-{{{}}}", negated_query));
+    }
+    if criteria.has_attachment == Some(true) {
+        parts.push("has:attachment".to_string());
+    }
+    if criteria.exclude_chats == Some(true) {
+        parts.push("-in:chats".to_string());
+    }
+    if let (Some(size), Some(size_comparison)) = (criteria.size, &criteria.size_comparison) {
+        parts.push(format!("{}:{}", size_comparison, size));
+    }
+    parts.join(" ")
+}
+
+/// Describes a filter's actions, showing labels by name
+pub async fn describe_action(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    action: &FilterAction,
+) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for (verb, label_ids) in [
+        ("add", &action.add_label_ids),
+        ("remove", &action.remove_label_ids),
+    ] {
+        let Some(label_ids) = label_ids.as_ref().filter(|label_ids| !label_ids.is_empty()) else {
+            continue;
+        };
+        let mut label_names = Vec::with_capacity(label_ids.len());
+        for label_id in label_ids {
+            label_names.push(
+                label_registry::label_name(hub, label_id)
+                    .await
+                    .unwrap_or(label_id.clone()),
+            );
+        }
+        parts.push(format!("{} labels {}", verb, label_names.join(", ")));
+    }
+    if let Some(forward) = &action.forward {
+        parts.push(format!("forward to {}", forward));
+    }
+    parts.join("; ")
+}
+
+/// Prints a filter's id, criteria and actions
+async fn print_filter(hub: &Gmail<HttpsConnector<HttpConnector>>, filter: &GmailFilter) {
+    println!(
+        "Filter {}\n    Matches: {}\n    Actions: {}",
+        filter.id.as_deref().unwrap_or("(no id)"),
+        filter
+            .criteria
+            .as_ref()
+            .map(describe_criteria)
+            .unwrap_or_default(),
+        match &filter.action {
+            Some(action) => describe_action(hub, action).await,
+            None => String::new(),
+        }
+    );
+}
+
+/// Prints every Gmail filter with its criteria and actions
+pub async fn print_filters(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filters = list_filters(hub).await?;
+    if filters.is_empty() {
+        println!("No Gmail filters");
+        return Ok(());
+    }
+    for filter in &filters {
+        print_filter(hub, filter).await;
+    }
+    println!("\n{} Gmail filters", filters.len());
+    Ok(())
+}
+
+/// Creates a Gmail filter, returning it with the id Gmail gave it
+pub async fn create_gmail_filter(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    filter: GmailFilter,
+) -> Result<GmailFilter, Box<dyn std::error::Error>> {
+    let (_res, filter) = hub
+        .users()
+        .settings_filters_create(filter, "me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await?;
+    Ok(filter)
+}
+
+/// Resolves a label name to its id. A missing label is created when create_missing is given, only
+/// being reported when it's a dry run (the name then stands in for the id)
+async fn resolve_label(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_name: &str,
+    create_missing: Option<bool>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let dry_run = match (mail::get_label_id(hub, label_name).await, create_missing) {
+        (Ok(label_id), _) => return Ok(label_id),
+        (Err(e), None) => return Err(e.into()),
+        (Err(_e), Some(dry_run)) => dry_run,
+    };
+    if dry_run {
+        println!("Dry run: would create label {}", label_name);
+        return Ok(label_name.to_string());
+    }
+    labels::create_label(hub, label_name, false).await?;
+    Ok(mail::get_label_id(hub, label_name).await?)
+}
+
+/// Turns filter actions into a Gmail filter action, resolving label names to ids. Missing labels
+/// to add are created when create_missing is given (see resolve_label)
+async fn action_from(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    actions: &GmailFilterActions,
+    create_missing: Option<bool>,
+) -> Result<FilterAction, Box<dyn std::error::Error>> {
+    let mut add_label_ids = Vec::with_capacity(actions.add.len() + 1);
+    for label_name in &actions.add {
+        add_label_ids.push(resolve_label(hub, label_name, create_missing).await?);
+    }
+    if actions.trash {
+        add_label_ids.push("TRASH".to_string());
+    }
+    let remove_label_ids = mail::get_label_ids(hub, &actions.remove).await?;
+    Ok(FilterAction {
+        add_label_ids: Some(add_label_ids).filter(|label_ids| !label_ids.is_empty()),
+        remove_label_ids: Some(remove_label_ids).filter(|label_ids| !label_ids.is_empty()),
+        forward: actions.forward.clone(),
+    })
+}
+
+/// Creates a Gmail filter from the filter options and actions. On a dry run the filter is only printed
+pub async fn create_filter(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    create: &GmailFilterCreate,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = GmailFilter {
+        action: Some(action_from(hub, &create.actions, None).await?),
+        criteria: Some(criteria_from_filter(&create.filter).await?),
+        id: None,
+    };
+
+    if dry_run {
+        println!("Dry run: would create this Gmail filter");
+        print_filter(hub, &filter).await;
+        return Ok(());
+    }
+    let filter = create_gmail_filter(hub, filter)
+        .await
+        .map_err(|e| format!("Could not create Gmail filter.\nError Received: {}", e))?;
+    println!("Created Gmail filter");
+    print_filter(hub, &filter).await;
+    Ok(())
+}
+
+/// Deletes the Gmail filters with the given ids. On a dry run the filters are only printed
+pub async fn delete_filters(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    filter_ids: &[String],
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filters = list_filters(hub).await?;
+    let mut deleted: usize = 0;
+    for filter_id in filter_ids {
+        let Some(filter) = filters
+            .iter()
+            .find(|filter| filter.id.as_ref() == Some(filter_id))
+        else {
+            println!("{} is a nonexistent Gmail filter id", filter_id);
+            continue;
+        };
+
+        if dry_run {
+            println!("Dry run: would delete");
+            print_filter(hub, filter).await;
+            continue;
+        }
+        match hub
+            .users()
+            .settings_filters_delete("me", filter_id)
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+        {
+            Ok(_res) => {
+                println!("Deleted");
+                print_filter(hub, filter).await;
+                deleted += 1;
+            }
+            Err(e) => println!(
+                "Could not delete Gmail filter {}.\nError Received: {}",
+                filter_id, e
+            ),
+        }
+    }
+    if !dry_run {
+        println!("\nDeleted {} Gmail filters", deleted);
+    }
+    Ok(())
+}
+
+/// Whether a filters file is a filter json file rather than a mailFilters.xml file
+fn is_json_file(file: &str) -> bool {
+    Path::new(file).extension().is_some_and(|ext| ext == "json")
+}
+
+/// What a filter's criteria match, the same for filters whose criteria only differ in case
+fn criteria_key(filter: &GmailFilter) -> String {
+    filter
+        .criteria
+        .as_ref()
+        .map(describe_criteria)
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// A single action (adding or removing a label, or forwarding) taken on what the criteria match
+fn action_key(criteria_key: &str, verb: &str, value: &str) -> String {
+    format!("{}|{} {}", criteria_key, verb, value.to_lowercase())
+}
+
+/// Every single action a filter takes along with its criteria. Filters do the same thing when they
+/// take the same actions between them, however those are split up across filters with the same criteria
+fn action_keys(filter: &GmailFilter) -> Vec<String> {
+    let criteria_key = criteria_key(filter);
+    let action = filter.action.clone().unwrap_or_default();
+    let mut action_keys: Vec<String> = Vec::new();
+    for (verb, label_ids) in [
+        ("add", action.add_label_ids),
+        ("remove", action.remove_label_ids),
+    ] {
+        for label_id in label_ids.unwrap_or_default() {
+            action_keys.push(action_key(&criteria_key, verb, &label_id));
+        }
+    }
+    if let Some(forward) = action.forward {
+        action_keys.push(action_key(&criteria_key, "forward", &forward));
+    }
+    action_keys
+}
+
+/// Merges filters with the same criteria into one taking all of their actions, since
+/// mailFilters.xml splits a filter adding several labels into one entry per label. Filters
+/// forwarding to different addresses are kept apart as a Gmail filter forwards to one address
+fn merge_filters(filters: Vec<GmailFilter>) -> Vec<GmailFilter> {
+    let mut merged: Vec<GmailFilter> = Vec::new();
+    for filter in filters {
+        let key = criteria_key(&filter);
+        let action = filter.action.clone().unwrap_or_default();
+        let same = merged.iter_mut().find(|other| {
+            let other_forward = other
+                .action
+                .as_ref()
+                .and_then(|other| other.forward.as_ref());
+            key == criteria_key(other)
+                && (other_forward.is_none()
+                    || action.forward.is_none()
+                    || other_forward == action.forward.as_ref())
+        });
+        let Some(other) = same else {
+            merged.push(filter);
+            continue;
+        };
+
+        let other_action = other.action.get_or_insert_with(FilterAction::default);
+        for (label_ids, other_label_ids) in [
+            (action.add_label_ids, &mut other_action.add_label_ids),
+            (action.remove_label_ids, &mut other_action.remove_label_ids),
+        ] {
+            for label_id in label_ids.unwrap_or_default() {
+                let other_label_ids = other_label_ids.get_or_insert_with(Vec::new);
+                if !other_label_ids.contains(&label_id) {
+                    other_label_ids.push(label_id);
+                }
+            }
+        }
+        if other_action.forward.is_none() {
+            other_action.forward = action.forward;
+        }
+    }
+    merged
+}
+
+/// The part of a filter taking the actions that aren't configured yet, or None when all of them are
+fn unconfigured_part(filter: &GmailFilter, configured: &BTreeSet<String>) -> Option<GmailFilter> {
+    let criteria_key = criteria_key(filter);
+    let action = filter.action.clone().unwrap_or_default();
+    let unconfigured = |verb: &str, label_ids: Option<Vec<String>>| {
+        Some(
+            label_ids
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|label_id| !configured.contains(&action_key(&criteria_key, verb, label_id)))
+                .collect::<Vec<String>>(),
+        )
+        .filter(|label_ids| !label_ids.is_empty())
+    };
+    let action = FilterAction {
+        add_label_ids: unconfigured("add", action.add_label_ids),
+        remove_label_ids: unconfigured("remove", action.remove_label_ids),
+        forward: action
+            .forward
+            .filter(|forward| !configured.contains(&action_key(&criteria_key, "forward", forward))),
+    };
+    if action.add_label_ids.is_none()
+        && action.remove_label_ids.is_none()
+        && action.forward.is_none()
+    {
+        return None;
+    }
+    Some(GmailFilter {
+        action: Some(action),
+        criteria: filter.criteria.clone(),
+        id: None,
+    })
+}
+
+/// Escapes text for an XML attribute value
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&apos;")
+        .replace('"', "&quot;")
+}
+
+/// The mailFilters.xml properties of filter criteria
+fn criteria_properties(criteria: &FilterCriteria) -> Vec<(&'static str, String)> {
+    let mut properties: Vec<(&str, String)> = Vec::new();
+    for (name, value) in [
+        ("from", &criteria.from),
+        ("to", &criteria.to),
+        ("subject", &criteria.subject),
+        ("hasTheWord", &criteria.query),
+        ("doesNotHaveTheWord", &criteria.negated_query),
+    ] {
+        if let Some(value) = value {
+            properties.push((name, value.clone()));
+        }
+    }
+    if criteria.has_attachment == Some(true) {
+        properties.push(("hasAttachment", "true".to_string()));
+    }
+    if criteria.exclude_chats == Some(true) {
+        properties.push(("excludeChats", "true".to_string()));
+    }
+    if let (Some(size), Some(size_comparison)) = (criteria.size, &criteria.size_comparison) {
+        let size_operator = if size_comparison == "smaller" {
+            "s_ss"
+        } else {
+            "s_sl"
+        };
+        properties.push(("size", size.to_string()));
+        properties.push(("sizeOperator", size_operator.to_string()));
+        properties.push(("sizeUnit", "s_sb".to_string()));
+    }
+    properties
+}
+
+/// The name of every label a Gmail filter adds or removes, by label id
+async fn filter_label_names(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    filter: &GmailFilter,
+) -> BTreeMap<String, String> {
+    let action = filter.action.clone().unwrap_or_default();
+    let mut label_names = BTreeMap::new();
+    for label_id in action
+        .add_label_ids
+        .into_iter()
+        .chain(action.remove_label_ids)
+        .flatten()
+    {
+        if let Some(label_name) = label_registry::label_name(hub, &label_id).await {
+            label_names.insert(label_id, label_name);
+        }
+    }
+    label_names
+}
+
+/// Turns a Gmail filter into mailFilters.xml entries, each a list of properties, showing labels by
+/// their name in label_names. The format only holds one label per entry, so a filter adding several
+/// labels becomes one entry per label, with the rest of its actions on the first
+fn filter_entries(
+    filter: &GmailFilter,
+    label_names: &BTreeMap<String, String>,
+) -> Vec<Vec<(&'static str, String)>> {
+    let criteria = filter
+        .criteria
+        .as_ref()
+        .map(criteria_properties)
+        .unwrap_or_default();
+    let action = filter.action.clone().unwrap_or_default();
+
+    let mut actions: Vec<(&str, String)> = Vec::new();
+    let mut entry_labels: Vec<String> = Vec::new();
+    for (label_ids, add) in [
+        (action.add_label_ids.unwrap_or_default(), true),
+        (action.remove_label_ids.unwrap_or_default(), false),
+    ] {
+        for label_id in label_ids {
+            if let Some((property, _label_id, _add)) =
+                FLAG_PROPERTIES
+                    .iter()
+                    .find(|(_property, flag_label_id, flag_add)| {
+                        *flag_label_id == label_id && *flag_add == add
+                    })
+            {
+                actions.push((property, "true".to_string()));
+            } else if let Some((smart_label, _label_id)) = SMART_LABELS
+                .iter()
+                .find(|(_smart_label, category)| add && *category == label_id)
+            {
+                actions.push(("smartLabelToApply", smart_label.to_string()));
+            } else if add {
+                entry_labels.push(label_names.get(&label_id).cloned().unwrap_or(label_id));
+            } else {
+                println!(
+                    "Filter {} removes label {}, which mailFilters.xml can't hold, so it's left out",
+                    filter.id.as_deref().unwrap_or_default(),
+                    label_names.get(&label_id).unwrap_or(&label_id)
+                );
+            }
+        }
+    }
+    if let Some(forward) = action.forward {
+        actions.push(("forwardTo", forward));
+    }
+
+    if entry_labels.is_empty() {
+        return vec![criteria.into_iter().chain(actions).collect()];
+    }
+    entry_labels
+        .into_iter()
+        .enumerate()
+        .map(|(index, label_name)| {
+            let mut entry = criteria.clone();
+            entry.push(("label", label_name));
+            if index == 0 {
+                entry.extend(actions.clone());
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Writes mailFilters.xml entries as an Atom feed, the format the Gmail web UI exports
+fn entries_to_xml(entries: &[Vec<(&'static str, String)>]) -> String {
+    let updated = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let mut xml = String::from(
+        "<?xml version='1.0' encoding='UTF-8'?>\n<feed xmlns='http://www.w3.org/2005/Atom' xmlns:apps='http://schemas.google.com/apps/2006'>\n",
+    );
+    xml.push_str("\t<title>Mail Filters</title>\n");
+    xml.push_str(&format!("\t<updated>{}</updated>\n", updated));
+    for entry in entries {
+        xml.push_str(
+            "\t<entry>\n\t\t<category term='filter'></category>\n\t\t<title>Mail Filter</title>\n",
+        );
+        xml.push_str(&format!(
+            "\t\t<updated>{}</updated>\n\t\t<content></content>\n",
+            updated
+        ));
+        for (name, value) in entry {
+            xml.push_str(&format!(
+                "\t\t<apps:property name='{}' value='{}'/>\n",
+                name,
+                xml_escape(value)
+            ));
+        }
+        xml.push_str("\t</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// Writes Gmail filters as an Atom mailFilters.xml feed
+async fn filters_to_xml(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    filters: &[GmailFilter],
+) -> String {
+    let mut entries = Vec::new();
+    for filter in filters {
+        entries.extend(filter_entries(
+            filter,
+            &filter_label_names(hub, filter).await,
+        ));
+    }
+    entries_to_xml(&entries)
+}
+
+/// Turns a Gmail filter into a filter json file entry, showing labels by name
+async fn filter_to_entry(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    filter: &GmailFilter,
+) -> FilterEntry {
+    let criteria = filter.criteria.clone().unwrap_or_default();
+    let action = filter.action.clone().unwrap_or_default();
+
+    // The query and negated query go in whole, which the filter options turn back into the same criteria
+    let (larger, smaller) = match (criteria.size, criteria.size_comparison.as_deref()) {
+        (Some(size), Some("smaller")) => (None, Some(size.max(0) as usize)),
+        (Some(size), _) => (Some(size.max(0) as usize), None),
+        (None, _) => (None, None),
+    };
+    let filter_options = Filter {
+        from: criteria.from.map(|from| vec![from]),
+        to: criteria.to.map(|to| vec![to]),
+        subject: criteria.subject.map(|subject| vec![subject]),
+        words: criteria.query.map(|query| vec![query]),
+        remove_words: criteria
+            .negated_query
+            .map(|negated_query| vec![negated_query]),
+        has: (criteria.has_attachment == Some(true)).then(|| vec!["attachment".to_string()]),
+        larger,
+        smaller,
+        ..Default::default()
+    };
+
+    let mut actions = GmailFilterActions {
+        forward: action.forward,
+        ..Default::default()
+    };
+    for label_id in action.add_label_ids.unwrap_or_default() {
+        if label_id == "TRASH" {
+            actions.trash = true;
+            continue;
+        }
+        let label_name = label_registry::label_name(hub, &label_id).await;
+        actions.add.push(label_name.unwrap_or(label_id));
+    }
+    for label_id in action.remove_label_ids.unwrap_or_default() {
+        let label_name = label_registry::label_name(hub, &label_id).await;
+        actions.remove.push(label_name.unwrap_or(label_id));
+    }
+
+    FilterEntry {
+        filter: filter_options,
+        actions,
+    }
+}
+
+/// Writes every Gmail filter to a mailFilters.xml file, or to a filter json file when the file
+/// has a .json extension
+pub async fn export_filters(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    file: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filters = list_filters(hub).await?;
+
+    let contents = if is_json_file(file) {
+        let mut entries = Vec::with_capacity(filters.len());
+        for filter in &filters {
+            let mut entry = serde_json::to_value(filter_to_entry(hub, filter).await)?;
+            // Filter options that aren't set are left out rather than written as null
+            if let Some(filter_options) = entry["filter"].as_object_mut() {
+                filter_options.retain(|_name, value| !value.is_null());
+            }
+            entries.push(entry);
+        }
+        serde_json::to_string_pretty(&entries)?
+    } else {
+        filters_to_xml(hub, &filters).await
+    };
+
+    fs::write(file, contents)
+        .map_err(|e| format!("Unable to write file {}.\nError Received: {}", file, e))?;
+    println!("Exported {} Gmail filters to {}", filters.len(), file);
+    Ok(())
+}
+
+/// Reads the property names and values of every entry of a mailFilters.xml file
+fn parse_mail_filters(xml: &str) -> Result<Vec<Properties>, Box<dyn std::error::Error>> {
+    let document = roxmltree::Document::parse(xml)
+        .map_err(|e| format!("XML was not well-formatted.\nError Received: {}", e))?;
+    let entries = document
+        .descendants()
+        .filter(|node| node.has_tag_name("entry"))
+        .map(|entry| {
+            entry
+                .children()
+                .filter(|node| node.has_tag_name("property"))
+                .filter_map(|property| {
+                    Some((
+                        property.attribute("name")?.to_string(),
+                        property.attribute("value")?.to_string(),
+                    ))
+                })
+                .collect()
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Turns the properties of a mailFilters.xml entry into a Gmail filter, along with the names of
+/// the labels it adds, which are left for the caller to resolve to ids
+fn filter_from_properties(
+    properties: &[(String, String)],
+) -> Result<(GmailFilter, Vec<String>), Box<dyn std::error::Error>> {
+    let mut criteria = FilterCriteria::default();
+    let mut action = FilterAction::default();
+    let mut add_label_ids: Vec<String> = Vec::new();
+    let mut remove_label_ids: Vec<String> = Vec::new();
+    let mut label_names: Vec<String> = Vec::new();
+    let mut size: Option<i64> = None;
+    let mut size_unit: i64 = 1;
+
+    for (name, value) in properties {
+        match name.as_str() {
+            "from" => criteria.from = Some(value.clone()),
+            "to" => criteria.to = Some(value.clone()),
+            "subject" => criteria.subject = Some(value.clone()),
+            "hasTheWord" => criteria.query = Some(value.clone()),
+            "doesNotHaveTheWord" => criteria.negated_query = Some(value.clone()),
+            "hasAttachment" => criteria.has_attachment = Some(value == "true"),
+            "excludeChats" => criteria.exclude_chats = Some(value == "true"),
+            "size" => {
+                size = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Size {} isn't a number", value))?,
+                )
+            }
+            "sizeOperator" => {
+                let size_comparison = if value == "s_ss" { "smaller" } else { "larger" };
+                criteria.size_comparison = Some(size_comparison.to_string());
+            }
+            "sizeUnit" => {
+                size_unit = SIZE_UNITS
+                    .iter()
+                    .find(|(unit, _bytes)| unit == value)
+                    .map(|(_unit, bytes)| *bytes)
+                    .ok_or(format!("Size unit {} is unknown", value))?
+            }
+            "label" => label_names.push(value.clone()),
+            "smartLabelToApply" => {
+                let (_smart_label, category) = SMART_LABELS
+                    .iter()
+                    .find(|(smart_label, _category)| smart_label == value)
+                    .ok_or(format!("Smart label {} is unknown", value))?;
+                add_label_ids.push(category.to_string());
+            }
+            "forwardTo" => action.forward = Some(value.clone()),
+            // Properties the Gmail web UI writes that don't change what the filter does
+            "sizeOperatorText" | "sizeUnitText" => {}
+            _ => match FLAG_PROPERTIES
+                .iter()
+                .find(|(property, _label_id, _add)| property == name)
+            {
+                Some((_property, label_id, add)) if value == "true" => {
+                    let label_ids = if *add {
+                        &mut add_label_ids
+                    } else {
+                        &mut remove_label_ids
+                    };
+                    label_ids.push(label_id.to_string());
+                }
+                Some(_flag) => {}
+                None => println!("Skipping unknown filter property {}", name),
+            },
+        }
+    }
+
+    if let Some(size) = size {
+        let bytes = i32::try_from(size * size_unit)
+            .map_err(|_| format!("Size {} is too large for a Gmail filter", size))?;
+        criteria.size = Some(bytes);
+        criteria.size_comparison.get_or_insert("larger".to_string());
+    }
+    action.add_label_ids = Some(add_label_ids).filter(|label_ids| !label_ids.is_empty());
+    action.remove_label_ids = Some(remove_label_ids).filter(|label_ids| !label_ids.is_empty());
+    let filter = GmailFilter {
+        action: Some(action),
+        criteria: Some(criteria),
+        id: None,
+    };
+    Ok((filter, label_names))
+}
+
+/// Resolves the names of labels a Gmail filter adds and adds them to it. Missing labels are
+/// created (only reported on a dry run)
+async fn add_labels(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    mut filter: GmailFilter,
+    label_names: &[String],
+    dry_run: bool,
+) -> Result<GmailFilter, Box<dyn std::error::Error>> {
+    if label_names.is_empty() {
+        return Ok(filter);
+    }
+    let action = filter.action.get_or_insert_with(FilterAction::default);
+    let add_label_ids = action.add_label_ids.get_or_insert_with(Vec::new);
+    for label_name in label_names {
+        add_label_ids.push(resolve_label(hub, label_name, Some(dry_run)).await?);
+    }
+    Ok(filter)
+}
+
+/// Reads the filters of a mailFilters.xml file, or a filter json file when the file has a .json
+/// extension. Entries that can't be turned into a Gmail filter are reported and left out
+async fn read_filters(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    file: &str,
+    dry_run: bool,
+) -> Result<Vec<GmailFilter>, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(file)
+        .map_err(|e| format!("Unable to read file {}.\nError Received: {}", file, e))?;
+
+    let mut filters = Vec::new();
+    if is_json_file(file) {
+        let entries: Vec<FilterEntry> = serde_json::from_str(&contents)
+            .map_err(|e| format!("JSON was not well-formatted.\nError Received: {}", e))?;
+        for (index, entry) in entries.into_iter().enumerate() {
+            let filter = match action_from(hub, &entry.actions, Some(dry_run)).await {
+                Ok(action) => {
+                    criteria_from_options(entry.filter)
+                        .await
+                        .map(|criteria| GmailFilter {
+                            action: Some(action),
+                            criteria: Some(criteria),
+                            id: None,
+                        })
+                }
+                Err(e) => Err(e),
+            };
+            match filter {
+                Ok(filter) => filters.push(filter),
+                Err(e) => println!("Skipping filter {} of {}: {}", index + 1, file, e),
+            }
+        }
+    } else {
+        for (index, properties) in parse_mail_filters(&contents)?.iter().enumerate() {
+            let filter = match filter_from_properties(properties) {
+                Ok((filter, label_names)) => add_labels(hub, filter, &label_names, dry_run).await,
+                Err(e) => Err(e),
+            };
+            match filter {
+                Ok(filter) => filters.push(filter),
+                Err(e) => println!("Skipping filter {} of {}: {}", index + 1, file, e),
+            }
+        }
+    }
+    Ok(filters)
+}
+
+/// Creates the filters of a mailFilters.xml file, or a filter json file when the file has a .json
+/// extension, that aren't configured yet. Filters in the file with the same criteria are merged
+/// first, and only the actions no existing filter with those criteria takes are created, so
+/// importing an export changes nothing. On a dry run the filters that would be created are only printed
+pub async fn import_filters(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    file: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filters = merge_filters(read_filters(hub, file, dry_run).await?);
+    let mut configured: BTreeSet<String> = list_filters(hub)
+        .await?
+        .iter()
+        .flat_map(action_keys)
+        .collect();
+
+    let mut created: usize = 0;
+    let mut skipped: usize = 0;
+    for filter in filters {
+        let Some(filter) = unconfigured_part(&filter, &configured) else {
+            skipped += 1;
+            continue;
+        };
+        configured.extend(action_keys(&filter));
+
+        if dry_run {
+            println!("Dry run: would create");
+            print_filter(hub, &filter).await;
+            created += 1;
+            continue;
+        }
+        match create_gmail_filter(hub, filter).await {
+            Ok(filter) => {
+                println!("Created");
+                print_filter(hub, &filter).await;
+                created += 1;
+            }
+            Err(e) => println!("Could not create Gmail filter.\nError Received: {}", e),
+        }
+    }
+
+    if dry_run {
+        println!("\nWould create {} Gmail filters", created);
+    } else {
+        println!("\nCreated {} Gmail filters", created);
+    }
+    println!("Skipped {} filters that are already configured", skipped);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    #[test]
+    fn export_then_import_creates_nothing() {
+        let filter = GmailFilter {
+            criteria: Some(FilterCriteria {
+                from: Some("News@Example.com".to_string()),
+                query: Some("weekly & <digest>".to_string()),
+                size: Some(2048),
+                size_comparison: Some("larger".to_string()),
+                ..Default::default()
+            }),
+            action: Some(FilterAction {
+                add_label_ids: strings(&["Label_1", "Label_2", "STARRED", "CATEGORY_SOCIAL"]),
+                remove_label_ids: strings(&["INBOX", "UNREAD", "Label_3"]),
+                forward: Some("me@example.com".to_string()),
+            }),
+            id: Some("filter_1".to_string()),
+        };
+        let label_names: BTreeMap<String, String> = [
+            ("Label_1", "News"),
+            ("Label_2", "News/Weekly"),
+            ("Label_3", "Later"),
+        ]
+        .into_iter()
+        .map(|(label_id, label_name)| (label_id.to_string(), label_name.to_string()))
+        .collect();
+
+        let xml = entries_to_xml(&filter_entries(&filter, &label_names));
+        let imported: Vec<GmailFilter> = parse_mail_filters(&xml)
+            .unwrap()
+            .iter()
+            .map(|properties| {
+                let (mut imported, entry_labels) = filter_from_properties(properties).unwrap();
+                let action = imported.action.get_or_insert_with(FilterAction::default);
+                for entry_label in entry_labels {
+                    let (label_id, _label_name) = label_names
+                        .iter()
+                        .find(|(_label_id, label_name)| **label_name == entry_label)
+                        .unwrap();
+                    action
+                        .add_label_ids
+                        .get_or_insert_with(Vec::new)
+                        .push(label_id.clone());
+                }
+                imported
+            })
+            .collect();
+        assert_eq!(imported.len(), 2);
+
+        let configured: BTreeSet<String> = action_keys(&filter).into_iter().collect();
+        let created: Vec<GmailFilter> = merge_filters(imported)
+            .iter()
+            .filter_map(|imported| unconfigured_part(imported, &configured))
+            .collect();
+        assert!(created.is_empty());
+    }
+
+    #[test]
+    fn import_only_creates_unconfigured_actions() {
+        let criteria = FilterCriteria {
+            from: Some("alerts@example.com".to_string()),
+            ..Default::default()
+        };
+        let configured = GmailFilter {
+            criteria: Some(criteria.clone()),
+            action: Some(FilterAction {
+                add_label_ids: strings(&["Label_1"]),
+                ..Default::default()
+            }),
+            id: Some("filter_1".to_string()),
+        };
+        let imported = GmailFilter {
+            criteria: Some(criteria),
+            action: Some(FilterAction {
+                add_label_ids: strings(&["Label_1", "Label_2"]),
+                ..Default::default()
+            }),
+            id: None,
+        };
+
+        let configured: BTreeSet<String> = action_keys(&configured).into_iter().collect();
+        let created = unconfigured_part(&imported, &configured).unwrap();
+        assert_eq!(created.action.unwrap().add_label_ids, strings(&["Label_2"]));
+    }
+
+    fn properties(properties: &[(&str, &str)]) -> Properties {
+        properties
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_mail_filter_entries() {
+        let xml = "<?xml version='1.0' encoding='UTF-8'?>
+<feed xmlns='http://www.w3.org/2005/Atom' xmlns:apps='http://schemas.google.com/apps/2006'>
+    <title>Mail Filters</title>
+    <entry>
+        <category term='filter'></category>
+        <apps:property name='from' value='news@example.com'/>
+        <apps:property name='hasTheWord' value='a &amp; b'/>
+        <apps:property name='label' value='News'/>
+    </entry>
+    <entry>
+        <apps:property name='subject' value='&quot;Invoice&quot;'/>
+        <apps:property name='shouldArchive' value='true'/>
+    </entry>
+</feed>";
+        assert_eq!(
+            parse_mail_filters(xml).unwrap(),
+            vec![
+                properties(&[
+                    ("from", "news@example.com"),
+                    ("hasTheWord", "a & b"),
+                    ("label", "News")
+                ]),
+                properties(&[("subject", "\"Invoice\""), ("shouldArchive", "true")]),
+            ]
+        );
+        assert!(parse_mail_filters("<feed>").is_err());
+    }
+
+    #[test]
+    fn converts_size_units_to_bytes() {
+        for (size_unit, bytes) in [("s_sb", 5), ("s_skb", 5 * 1024), ("s_smb", 5 * 1024 * 1024)] {
+            let (filter, _label_names) = filter_from_properties(&properties(&[
+                ("size", "5"),
+                ("sizeOperator", "s_ss"),
+                ("sizeUnit", size_unit),
+            ]))
+            .unwrap();
+            let criteria = filter.criteria.unwrap();
+            assert_eq!(criteria.size, Some(bytes));
+            assert_eq!(criteria.size_comparison.as_deref(), Some("smaller"));
+        }
+
+        // The comparison defaults to larger, and sizes past an i32 of bytes are refused
+        let (filter, _label_names) = filter_from_properties(&properties(&[("size", "1")])).unwrap();
+        assert_eq!(
+            filter.criteria.unwrap().size_comparison.as_deref(),
+            Some("larger")
+        );
+        assert!(
+            filter_from_properties(&properties(&[("size", "4096"), ("sizeUnit", "s_smb")]))
+                .is_err()
+        );
+        assert!(filter_from_properties(&properties(&[("sizeUnit", "s_sgb")])).is_err());
+    }
+
+    #[test]
+    fn escapes_xml_attribute_values() {
+        assert_eq!(
+            xml_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(xml_escape("plain"), "plain");
+    }
+}
@@ -0,0 +1,514 @@
+extern crate google_gmail1 as gmail1;
+
+use chrono::Local;
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::mail_service as mail;
+
+/// Append-only journal holding every successful trash, untrash and label change, one JSON entry per line
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+
+/// Run id that every entry recorded by this process is written under
+static RUN_ID: Mutex<Option<String>> = Mutex::new(None);
+
+/// What was done to the messages or threads of a journal entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "op")]
+pub enum JournalOp {
+    Trash,
+    Untrash,
+    Modify {
+        add_label_ids: Vec<String>,
+        remove_label_ids: Vec<String>,
+    },
+}
+
+/// Whether the ids of a journal entry are message ids or thread ids
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JournalTarget {
+    Messages,
+    Threads,
+}
+
+/// A single line of the journal: the ids that an operation succeeded on during a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub time: String,
+    pub target: JournalTarget,
+    #[serde(flatten)]
+    pub op: JournalOp,
+    pub ids: Vec<String>,
+}
+
+/// Returns the id of the current run, making one from the start time and process id if
+/// there isn't one yet
+fn run_id() -> String {
+    let mut run_id = RUN_ID.lock().unwrap();
+    run_id
+        .get_or_insert_with(|| {
+            format!(
+                "{}-{}",
+                Local::now().format("%Y%m%dT%H%M%S"),
+                std::process::id()
+            )
+        })
+        .clone()
+}
+
+/// Starts a new run so the entries recorded from now on can be undone apart from earlier ones
+/// in the same process (e.g. each job run of the daemon)
+pub fn start_run() {
+    *RUN_ID.lock().unwrap() = None;
+}
+
+/// Appends the ids that an operation succeeded on to the journal under the current run id
+pub fn record(target: JournalTarget, op: JournalOp, ids: &[String]) {
+    if ids.is_empty() {
+        return;
+    }
+
+    let entry = JournalEntry {
+        run_id: run_id(),
+        time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        target,
+        op,
+        ids: ids.to_vec(),
+    };
+    if let Err(e) = append_entry(JOURNAL_FILE, &entry) {
+        println!("{}", e);
+    }
+}
+
+/// Appends an entry to a journal file as a single JSON line
+fn append_entry(journal_file: &str, entry: &JournalEntry) -> Result<(), String> {
+    let line = serde_json::to_string(entry).map_err(|e| {
+        format!(
+            "Couldn't record {} ids in the journal.\nError Received: {}",
+            entry.ids.len(),
+            e
+        )
+    })?;
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_file)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|e| {
+            format!(
+                "Couldn't write to journal file {}.\nError Received: {}",
+                journal_file, e
+            )
+        })
+}
+
+/// The label changes a modify command actually makes on the ids it goes through: for each label
+/// id added the ids that didn't carry it yet, and for each label id removed the ids that carried it.
+/// They're journaled one label id at a time so undo never strips a label a message already had or
+/// brings back one it never had
+#[derive(Debug, Clone, Default)]
+pub struct LabelChanges {
+    added: Vec<(String, BTreeSet<String>)>,
+    removed: Vec<(String, BTreeSet<String>)>,
+}
+
+impl LabelChanges {
+    /// Works out the changes on the ids from which of them carry each label id beforehand.
+    /// A label id missing from carrying is taken to change on every id
+    pub fn new(
+        ids: &BTreeSet<String>,
+        add_label_ids: &[String],
+        remove_label_ids: &[String],
+        carrying: &BTreeMap<String, BTreeSet<String>>,
+    ) -> Self {
+        let added = add_label_ids
+            .iter()
+            .map(|label_id| {
+                let changed = match carrying.get(label_id) {
+                    Some(carrying) => ids.difference(carrying).cloned().collect(),
+                    None => ids.clone(),
+                };
+                (label_id.clone(), changed)
+            })
+            .collect();
+        let removed = remove_label_ids
+            .iter()
+            .map(|label_id| {
+                let changed = match carrying.get(label_id) {
+                    Some(carrying) => ids.intersection(carrying).cloned().collect(),
+                    None => ids.clone(),
+                };
+                (label_id.clone(), changed)
+            })
+            .collect();
+        LabelChanges { added, removed }
+    }
+
+    /// Works out the changes from the label ids each id carries beforehand, for when those come
+    /// back from getting the ids rather than from listing each label id
+    pub fn from_carried(
+        carried: &BTreeMap<String, Vec<String>>,
+        add_label_ids: &[String],
+        remove_label_ids: &[String],
+    ) -> Self {
+        let ids: BTreeSet<String> = carried.keys().cloned().collect();
+        let mut carrying: BTreeMap<String, BTreeSet<String>> = add_label_ids
+            .iter()
+            .chain(remove_label_ids)
+            .map(|label_id| (label_id.clone(), BTreeSet::new()))
+            .collect();
+        for (id, label_ids) in carried {
+            for label_id in label_ids {
+                if let Some(carrying_ids) = carrying.get_mut(label_id) {
+                    carrying_ids.insert(id.clone());
+                }
+            }
+        }
+        LabelChanges::new(&ids, add_label_ids, remove_label_ids, &carrying)
+    }
+
+    /// Changes made on every one of the ids, as when undoing a journal entry
+    pub fn on_every(ids: &[String], add_label_ids: &[String], remove_label_ids: &[String]) -> Self {
+        let ids: BTreeSet<String> = ids.iter().cloned().collect();
+        LabelChanges::new(&ids, add_label_ids, remove_label_ids, &BTreeMap::new())
+    }
+
+    /// Every label id added
+    pub fn add_label_ids(&self) -> Vec<String> {
+        self.added
+            .iter()
+            .map(|(label_id, _ids)| label_id.clone())
+            .collect()
+    }
+
+    /// Every label id removed
+    pub fn remove_label_ids(&self) -> Vec<String> {
+        self.removed
+            .iter()
+            .map(|(label_id, _ids)| label_id.clone())
+            .collect()
+    }
+
+    /// The ids that at least one label id changes on
+    pub fn changed_ids(&self) -> BTreeSet<String> {
+        self.added
+            .iter()
+            .chain(&self.removed)
+            .flat_map(|(_label_id, ids)| ids.iter().cloned())
+            .collect()
+    }
+
+    /// Journals the changes made on the given ids, one entry per label id holding only the ids
+    /// whose labels it changed
+    pub fn record(&self, target: JournalTarget, ids: &[String]) {
+        let changed_ids = |changed: &BTreeSet<String>| -> Vec<String> {
+            ids.iter()
+                .filter(|id| changed.contains(*id))
+                .cloned()
+                .collect()
+        };
+        for (label_id, changed) in &self.added {
+            let op = JournalOp::Modify {
+                add_label_ids: vec![label_id.clone()],
+                remove_label_ids: Vec::new(),
+            };
+            record(target, op, &changed_ids(changed));
+        }
+        for (label_id, changed) in &self.removed {
+            let op = JournalOp::Modify {
+                add_label_ids: Vec::new(),
+                remove_label_ids: vec![label_id.clone()],
+            };
+            record(target, op, &changed_ids(changed));
+        }
+    }
+}
+
+/// Reads every entry of the journal in the order they were recorded
+fn read_journal() -> Result<Vec<JournalEntry>, Box<dyn std::error::Error>> {
+    read_journal_file(JOURNAL_FILE)
+}
+
+/// Reads every entry of a journal file in the order they were recorded
+fn read_journal_file(journal_file: &str) -> Result<Vec<JournalEntry>, Box<dyn std::error::Error>> {
+    let journal_string = fs::read_to_string(journal_file).map_err(|e| {
+        format!(
+            "Unable to read journal file {}.\nError Received: {}",
+            journal_file, e
+        )
+    })?;
+
+    let mut entries = Vec::new();
+    for (line_num, line) in journal_string.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry = serde_json::from_str(line).map_err(|e| {
+            format!(
+                "Line {} of the journal was not well-formatted.\nError Received: {}",
+                line_num + 1,
+                e
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Describes a journal entry's operation, e.g. "trashed 20 messages"
+fn describe_entry(entry: &JournalEntry) -> String {
+    let noun = match entry.target {
+        JournalTarget::Messages => "messages",
+        JournalTarget::Threads => "threads",
+    };
+    match &entry.op {
+        JournalOp::Trash => format!("trashed {} {}", entry.ids.len(), noun),
+        JournalOp::Untrash => format!("untrashed {} {}", entry.ids.len(), noun),
+        JournalOp::Modify {
+            add_label_ids,
+            remove_label_ids,
+        } => format!(
+            "added labels {:?} and removed labels {:?} on {} {}",
+            add_label_ids,
+            remove_label_ids,
+            entry.ids.len(),
+            noun
+        ),
+    }
+}
+
+/// Prints every run in the journal with when it started and what it did
+pub fn list_runs() -> Result<(), Box<dyn std::error::Error>> {
+    let entries = read_journal()?;
+    if entries.is_empty() {
+        println!("The journal has no runs");
+        return Ok(());
+    }
+
+    let mut run_ids: Vec<&str> = Vec::new();
+    for entry in &entries {
+        if !run_ids.contains(&entry.run_id.as_str()) {
+            run_ids.push(&entry.run_id);
+        }
+    }
+
+    for run_id in run_ids {
+        let run_entries: Vec<&JournalEntry> = entries
+            .iter()
+            .filter(|entry| entry.run_id == run_id)
+            .collect();
+        println!("{} (started {})", run_id, run_entries[0].time);
+        for entry in run_entries {
+            println!("    {}", describe_entry(entry));
+        }
+    }
+    Ok(())
+}
+
+/// Replays the inverse of a single journal entry. Returns how many ids it was undone on
+async fn undo_entry(hub: &Gmail<HttpsConnector<HttpConnector>>, entry: &JournalEntry) -> usize {
+    match (&entry.op, entry.target) {
+        (JournalOp::Trash, JournalTarget::Messages) => mail::untrash_msg_ids(hub, &entry.ids).await,
+        (JournalOp::Trash, JournalTarget::Threads) => {
+            mail::untrash_thread_ids(hub, &entry.ids).await
+        }
+        (JournalOp::Untrash, JournalTarget::Messages) => mail::trash_msg_ids(hub, &entry.ids).await,
+        (JournalOp::Untrash, JournalTarget::Threads) => {
+            mail::trash_thread_ids(hub, &entry.ids).await
+        }
+        (
+            JournalOp::Modify {
+                add_label_ids,
+                remove_label_ids,
+            },
+            JournalTarget::Messages,
+        ) => {
+            let changes = LabelChanges::on_every(&entry.ids, remove_label_ids, add_label_ids);
+            mail::modify_msg_ids(hub, &entry.ids, &changes).await
+        }
+        (
+            JournalOp::Modify {
+                add_label_ids,
+                remove_label_ids,
+            },
+            JournalTarget::Threads,
+        ) => {
+            let changes = LabelChanges::on_every(&entry.ids, remove_label_ids, add_label_ids);
+            mail::modify_thread_ids(hub, &entry.ids, &changes).await
+        }
+    }
+}
+
+/// Replays the inverse operations of the given run, or of the most recent run if none is given,
+/// going through its entries from last to first. The undo is itself journaled as a new run.
+/// On a dry run the inverse operations are only listed
+pub async fn undo_run(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    run_id: &Option<String>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = read_journal()?;
+    let run_id = match run_id {
+        Some(run_id) => run_id.clone(),
+        None => entries
+            .last()
+            .map(|entry| entry.run_id.clone())
+            .ok_or("The journal has no runs to undo")?,
+    };
+
+    let run_entries: Vec<&JournalEntry> = entries
+        .iter()
+        .filter(|entry| entry.run_id == run_id)
+        .collect();
+    if run_entries.is_empty() {
+        return Err(format!("The journal has no run with id {}", run_id).into());
+    }
+
+    for entry in run_entries.into_iter().rev() {
+        if dry_run {
+            println!("Would undo: {}", describe_entry(entry));
+            continue;
+        }
+
+        let undone = undo_entry(hub, entry).await;
+        println!(
+            "Undid {} of {}: {}",
+            undone,
+            entry.ids.len(),
+            describe_entry(entry)
+        );
+    }
+
+    if !dry_run {
+        println!("Undid run {}", run_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn entries_read_back_as_recorded() {
+        let journal_file =
+            std::env::temp_dir().join(format!("journal-round-trip-{}.jsonl", std::process::id()));
+        let journal_file = journal_file.to_str().unwrap();
+        let _ = fs::remove_file(journal_file);
+
+        let entries = [
+            JournalEntry {
+                run_id: "run-1".to_string(),
+                time: "2024-01-10 09:30:00".to_string(),
+                target: JournalTarget::Messages,
+                op: JournalOp::Trash,
+                ids: strings(&["msg_1", "msg_2"]),
+            },
+            JournalEntry {
+                run_id: "run-2".to_string(),
+                time: "2024-01-10 09:31:00".to_string(),
+                target: JournalTarget::Threads,
+                op: JournalOp::Modify {
+                    add_label_ids: strings(&["STARRED"]),
+                    remove_label_ids: strings(&["INBOX"]),
+                },
+                ids: strings(&["thread_1"]),
+            },
+        ];
+        for entry in &entries {
+            append_entry(journal_file, entry).unwrap();
+        }
+        let read = read_journal_file(journal_file);
+        fs::remove_file(journal_file).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(read.len(), entries.len());
+        for (read, recorded) in read.iter().zip(&entries) {
+            assert_eq!(read.run_id, recorded.run_id);
+            assert_eq!(read.time, recorded.time);
+            assert_eq!(read.target, recorded.target);
+            assert_eq!(describe_entry(read), describe_entry(recorded));
+            assert_eq!(read.ids, recorded.ids);
+        }
+    }
+
+    #[test]
+    fn label_changes_only_hold_ids_that_change() {
+        let ids: BTreeSet<String> = strings(&["msg_1", "msg_2", "msg_3"]).into_iter().collect();
+        let carrying: BTreeMap<String, BTreeSet<String>> = [
+            ("STARRED", strings(&["msg_1"])),
+            ("INBOX", strings(&["msg_1", "msg_2"])),
+        ]
+        .into_iter()
+        .map(|(label_id, ids)| (label_id.to_string(), ids.into_iter().collect()))
+        .collect();
+
+        let changes = LabelChanges::new(
+            &ids,
+            &strings(&["STARRED"]),
+            &strings(&["INBOX"]),
+            &carrying,
+        );
+        assert_eq!(
+            changes.added,
+            vec![(
+                "STARRED".to_string(),
+                strings(&["msg_2", "msg_3"]).into_iter().collect()
+            )]
+        );
+        assert_eq!(
+            changes.removed,
+            vec![(
+                "INBOX".to_string(),
+                strings(&["msg_1", "msg_2"]).into_iter().collect()
+            )]
+        );
+        assert_eq!(changes.changed_ids(), ids);
+
+        // A label id that couldn't be checked counts as changed on every id
+        let changes = LabelChanges::new(&ids, &strings(&["UNREAD"]), &[], &carrying);
+        assert_eq!(changes.changed_ids(), ids);
+    }
+
+    #[test]
+    fn label_changes_from_carried_label_ids() {
+        let carried: BTreeMap<String, Vec<String>> = [
+            ("msg_1", strings(&["INBOX", "STARRED"])),
+            ("msg_2", strings(&["INBOX"])),
+            ("msg_3", strings(&["CATEGORY_UPDATES"])),
+        ]
+        .into_iter()
+        .map(|(id, label_ids)| (id.to_string(), label_ids))
+        .collect();
+
+        let changes =
+            LabelChanges::from_carried(&carried, &strings(&["STARRED"]), &strings(&["INBOX"]));
+        assert_eq!(
+            changes.added,
+            vec![(
+                "STARRED".to_string(),
+                strings(&["msg_2", "msg_3"]).into_iter().collect()
+            )]
+        );
+        assert_eq!(
+            changes.removed,
+            vec![(
+                "INBOX".to_string(),
+                strings(&["msg_1", "msg_2"]).into_iter().collect()
+            )]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+extern crate google_gmail1 as gmail1;
+
+use chrono::{Duration, Local};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::mail_service as mail;
+
+/// Label cache written when --label-cache-ttl is given, so later runs can skip labels.list
+pub const LABEL_CACHE_FILE: &str = "label_cache.json";
+
+/// Most label names suggested when a label name doesn't exist
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Labels of the authenticated email, loaded once per session by the first lookup
+static REGISTRY: Mutex<Option<LabelRegistry>> = Mutex::new(None);
+
+/// How long the label cache file stays fresh; the file isn't used when this is None
+static CACHE_TTL: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Every label name and id, indexed by lowercased name and by id
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LabelRegistry {
+    /// Unix timestamp of when labels.list was called
+    fetched_at: i64,
+
+    /// Lowercased label name to the label's (name, id). Gmail label names are unique regardless of case
+    by_name: BTreeMap<String, (String, String)>,
+
+    /// Label id to label name
+    by_id: BTreeMap<String, String>,
+}
+
+impl LabelRegistry {
+    fn new(labels: BTreeMap<String, String>) -> Self {
+        let mut registry = LabelRegistry {
+            fetched_at: Local::now().timestamp(),
+            ..Default::default()
+        };
+        for (label_name, label_id) in labels {
+            registry.by_name.insert(
+                label_name.to_lowercase(),
+                (label_name.clone(), label_id.clone()),
+            );
+            registry.by_id.insert(label_id, label_name);
+        }
+        registry
+    }
+
+    /// Resolves a label name (in any case) to its id. A label id is passed through as is
+    fn resolve(&self, name_or_id: &str) -> Option<String> {
+        if let Some((_label_name, label_id)) = self.by_name.get(&name_or_id.to_lowercase()) {
+            return Some(label_id.clone());
+        }
+        self.by_id
+            .contains_key(name_or_id)
+            .then(|| name_or_id.to_string())
+    }
+
+    /// The existing label names closest to a misspelled one, closest first
+    fn suggestions(&self, label_name: &str) -> Vec<&str> {
+        let wanted = label_name.to_lowercase();
+        let max_distance = (wanted.chars().count() / 3).max(2);
+
+        let mut close: Vec<(usize, &str)> = self
+            .by_name
+            .iter()
+            .filter_map(|(lowercase_name, (label_name, _label_id))| {
+                let distance = edit_distance(&wanted, lowercase_name);
+                let nested = lowercase_name
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|leaf| leaf == wanted);
+                if distance <= max_distance || nested {
+                    Some((distance, label_name.as_str()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        close.sort();
+        close
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_distance, label_name)| label_name)
+            .collect()
+    }
+
+    /// Error message for a label name that doesn't exist, with the closest existing names
+    fn nonexistent_message(&self, label_name: &str) -> String {
+        let suggestions = self.suggestions(label_name);
+        if suggestions.is_empty() {
+            format!("{} is a nonexistent label name", label_name)
+        } else {
+            format!(
+                "{} is a nonexistent label name. Did you mean {}?",
+                label_name,
+                suggestions.join(", ")
+            )
+        }
+    }
+}
+
+/// Levenshtein distance between two strings, counted in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Keeps the labels in a cache file for the given time, so runs within it skip labels.list
+pub fn set_cache_ttl(ttl: Option<Duration>) {
+    *CACHE_TTL.lock().unwrap() = ttl;
+}
+
+/// Drops the labels loaded this session, and the cache file, so the next lookup lists the labels
+/// again. Called after labels are created, renamed or deleted
+pub fn invalidate() {
+    *REGISTRY.lock().unwrap() = None;
+    if Path::new(LABEL_CACHE_FILE).exists() {
+        if let Err(e) = fs::remove_file(LABEL_CACHE_FILE) {
+            println!(
+                "Unable to remove file {}.\nError Received: {}",
+                LABEL_CACHE_FILE, e
+            );
+        }
+    }
+}
+
+/// Drops the labels loaded this session while keeping a fresh cache file, so a long running
+/// process (e.g. each job run of the daemon) notices labels changed elsewhere once the file expires
+pub fn start_session() {
+    *REGISTRY.lock().unwrap() = None;
+}
+
+/// Reads the cache file if it exists and is younger than the ttl
+fn read_cache(ttl: Duration) -> Option<LabelRegistry> {
+    let cache_string = fs::read_to_string(LABEL_CACHE_FILE).ok()?;
+    let registry: LabelRegistry = serde_json::from_str(&cache_string).ok()?;
+    let age = Local::now().timestamp() - registry.fetched_at;
+    (0..ttl.num_seconds()).contains(&age).then_some(registry)
+}
+
+/// Writes the labels to the cache file
+fn write_cache(registry: &LabelRegistry) {
+    let result = serde_json::to_string(registry)
+        .map_err(|e| e.to_string())
+        .and_then(|cache_string| {
+            fs::write(LABEL_CACHE_FILE, cache_string).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        println!(
+            "Unable to write file {}.\nError Received: {}",
+            LABEL_CACHE_FILE, e
+        );
+    }
+}
+
+/// Loads the labels into the registry if this session hasn't yet, from the cache file when it's
+/// fresh and from labels.list otherwise
+async fn ensure_loaded(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if REGISTRY.lock().unwrap().is_some() {
+        return Ok(());
+    }
+
+    let ttl = *CACHE_TTL.lock().unwrap();
+    let registry = match ttl.and_then(read_cache) {
+        Some(registry) => registry,
+        None => {
+            let registry = LabelRegistry::new(mail::list_labels(hub).await?);
+            if ttl.is_some() {
+                write_cache(&registry);
+            }
+            registry
+        }
+    };
+    *REGISTRY.lock().unwrap() = Some(registry);
+    Ok(())
+}
+
+/// Runs a lookup against the registry, loading it first if needed
+async fn with_registry<T>(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    lookup: impl FnOnce(&LabelRegistry) -> T,
+) -> Result<T, String> {
+    ensure_loaded(hub)
+        .await
+        .map_err(|e| format!("Labels list unsuccessfully received.\nError Received {}", e))?;
+    let registry = REGISTRY.lock().unwrap();
+    registry
+        .as_ref()
+        .map(lookup)
+        .ok_or("Labels list was dropped while being read".to_string())
+}
+
+/// Resolves a label name (case-insensitively) or a label id to the label's id.
+/// The error names the closest existing labels when the name doesn't exist
+pub async fn label_id(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_name: &str,
+) -> Result<String, String> {
+    with_registry(hub, |registry| {
+        registry
+            .resolve(label_name)
+            .ok_or_else(|| registry.nonexistent_message(label_name))
+    })
+    .await?
+}
+
+/// Retrieves the name of the label with the given id
+pub async fn label_name(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_id: &str,
+) -> Option<String> {
+    with_registry(hub, |registry| registry.by_id.get(label_id).cloned())
+        .await
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> LabelRegistry {
+        LabelRegistry::new(
+            [
+                ("INBOX", "INBOX"),
+                ("Receipts", "Label_1"),
+                ("Work/Projects", "Label_2"),
+                ("Work/Reports", "Label_3"),
+                ("Travel", "Label_4"),
+            ]
+            .into_iter()
+            .map(|(label_name, label_id)| (label_name.to_string(), label_id.to_string()))
+            .collect(),
+        )
+    }
+
+    #[test]
+    fn counts_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("receipts", "reciepts"), 2);
+        assert_eq!(edit_distance("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn resolves_names_in_any_case_and_ids() {
+        let registry = registry();
+        assert_eq!(registry.resolve("receipts"), Some("Label_1".to_string()));
+        assert_eq!(
+            registry.resolve("WORK/PROJECTS"),
+            Some("Label_2".to_string())
+        );
+        assert_eq!(registry.resolve("inbox"), Some("INBOX".to_string()));
+        assert_eq!(registry.resolve("Label_4"), Some("Label_4".to_string()));
+        assert_eq!(registry.resolve("Label_9"), None);
+        assert_eq!(registry.resolve("Invoices"), None);
+    }
+
+    #[test]
+    fn suggests_close_and_nested_names() {
+        let registry = registry();
+        assert_eq!(registry.suggestions("Reciepts"), vec!["Receipts"]);
+        assert_eq!(registry.suggestions("travle"), vec!["Travel"]);
+        // A leaf name finds its nested label even when the full names are far apart
+        assert_eq!(registry.suggestions("Projects"), vec!["Work/Projects"]);
+        assert!(registry.suggestions("Newsletters").is_empty());
+        assert_eq!(
+            registry.nonexistent_message("Reciepts"),
+            "Reciepts is a nonexistent label name. Did you mean Receipts?"
+        );
+    }
+}
@@ -0,0 +1,560 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::api::{Label, LabelColor};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::cmd_args::LabelsFormat;
+use crate::label_registry;
+
+/// Lists every label within authenticated email with all of its fields that labels.list returns
+pub async fn list_all_labels(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<Vec<Label>, Box<dyn std::error::Error>> {
+    let (_res, labels_list) = hub
+        .users()
+        .labels_list("me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await?;
+    Ok(labels_list.labels.unwrap_or_default())
+}
+
+/// The fields of a label shown by the label listing
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LabelDetails {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    type_: String,
+    messages_total: i32,
+    messages_unread: i32,
+    threads_total: i32,
+    label_list_visibility: String,
+    message_list_visibility: String,
+}
+
+impl LabelDetails {
+    fn from_label(label: Label) -> Self {
+        LabelDetails {
+            id: label.id.unwrap_or_default(),
+            name: label.name.unwrap_or_default(),
+            type_: label.type_.unwrap_or_default(),
+            messages_total: label.messages_total.unwrap_or(0),
+            messages_unread: label.messages_unread.unwrap_or(0),
+            threads_total: label.threads_total.unwrap_or(0),
+            label_list_visibility: label.label_list_visibility.unwrap_or_default(),
+            message_list_visibility: label.message_list_visibility.unwrap_or_default(),
+        }
+    }
+}
+
+/// Quotes a CSV field when it holds a comma, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Prints every label with its id, type, message and thread counts and visibility in the given
+/// format. labels.list leaves out the counts, so each label is fetched with labels.get
+pub async fn print_label_details(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    format: LabelsFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let labels = list_all_labels(hub).await?;
+
+    let mut details: Vec<LabelDetails> = Vec::with_capacity(labels.len());
+    for label in labels {
+        let Some(label_id) = label.id else {
+            continue;
+        };
+        let (_res, label) = hub
+            .users()
+            .labels_get("me", &label_id)
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+            .map_err(|e| {
+                format!(
+                    "Could not read label with id {}.\nError Received: {}",
+                    label_id, e
+                )
+            })?;
+        details.push(LabelDetails::from_label(label));
+    }
+    // System labels come first, then user labels by name
+    details.sort_by(|a, b| (&a.type_, &a.name).cmp(&(&b.type_, &b.name)));
+
+    match format {
+        LabelsFormat::Json => println!("{}", serde_json::to_string_pretty(&details)?),
+        LabelsFormat::Csv => {
+            println!("id,name,type,messagesTotal,messagesUnread,threadsTotal,labelListVisibility,messageListVisibility");
+            for label in &details {
+                println!(
+                    "{},{},{},{},{},{},{},{}",
+                    csv_field(&label.id),
+                    csv_field(&label.name),
+                    label.type_,
+                    label.messages_total,
+                    label.messages_unread,
+                    label.threads_total,
+                    label.label_list_visibility,
+                    label.message_list_visibility
+                );
+            }
+        }
+        LabelsFormat::Table => {
+            let name_width = details
+                .iter()
+                .map(|label| label.name.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("Name".len());
+            let id_width = details
+                .iter()
+                .map(|label| label.id.len())
+                .max()
+                .unwrap_or(0)
+                .max("Id".len());
+            println!(
+                "{:name_width$}  {:id_width$}  {:6}  {:>8}  {:>6}  {:>7}  Visibility (list/messages)",
+                "Name", "Id", "Type", "Messages", "Unread", "Threads"
+            );
+            for label in &details {
+                println!(
+                    "{:name_width$}  {:id_width$}  {:6}  {:>8}  {:>6}  {:>7}  {}/{}",
+                    label.name,
+                    label.id,
+                    label.type_,
+                    label.messages_total,
+                    label.messages_unread,
+                    label.threads_total,
+                    label.label_list_visibility,
+                    label.message_list_visibility
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds the label with the given name in any case, since Gmail label names are unique regardless of case
+fn find_label<'a>(labels: &'a [Label], name: &str) -> Option<&'a Label> {
+    let name = name.to_lowercase();
+    labels.iter().find(|label| {
+        label
+            .name
+            .as_ref()
+            .map(|label_name| label_name.to_lowercase())
+            == Some(name.clone())
+    })
+}
+
+/// Finds the label with the given name or id, erroring with the closest existing label names
+/// when there's none
+async fn find_existing_label<'a>(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    labels: &'a [Label],
+    name: &str,
+) -> Result<&'a Label, Box<dyn std::error::Error>> {
+    let label_id = label_registry::label_id(hub, name).await?;
+    labels
+        .iter()
+        .find(|label| label.id.as_deref() == Some(label_id.as_str()))
+        .ok_or_else(|| format!("{} is a nonexistent label name", name).into())
+}
+
+/// Creates a label with the given name, also creating every missing parent label of a
+/// `/`-nested name (e.g. Work and Work/Projects for Work/Projects/Rust).
+/// On a dry run the labels that would be created are only printed
+pub async fn create_label(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    name: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let labels = list_all_labels(hub).await?;
+    if find_label(&labels, name).is_some() {
+        return Err(format!("Label {} already exists", name).into());
+    }
+
+    if !dry_run {
+        label_registry::invalidate();
+    }
+    let parts: Vec<&str> = name.split('/').collect();
+    for depth in 1..=parts.len() {
+        let label_name = parts[..depth].join("/");
+        if find_label(&labels, &label_name).is_some() {
+            continue;
+        }
+        if dry_run {
+            println!("Dry run: would create label {}", label_name);
+            continue;
+        }
+
+        let request = Label {
+            name: Some(label_name.clone()),
+            label_list_visibility: Some("labelShow".to_string()),
+            message_list_visibility: Some("show".to_string()),
+            ..Default::default()
+        };
+        hub.users()
+            .labels_create(request, "me")
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+            .map_err(|e| {
+                format!(
+                    "Could not create label {}.\nError Received: {}",
+                    label_name, e
+                )
+            })?;
+        println!("Created label {}", label_name);
+    }
+    Ok(())
+}
+
+/// The label with the given name followed by every label nested under it
+fn subtree<'a>(labels: &'a [Label], name: &str) -> Vec<&'a Label> {
+    let child_prefix = format!("{}/", name);
+    let mut subtree: Vec<&Label> = labels
+        .iter()
+        .filter(|label| {
+            label.name.as_deref().is_some_and(|label_name| {
+                label_name == name || label_name.starts_with(&child_prefix)
+            })
+        })
+        .collect();
+    subtree.sort_by_key(|label| label.name.clone());
+    subtree
+}
+
+/// Renames a label, moving every label nested under it along with it
+/// (e.g. renaming Work to Jobs also renames Work/Projects to Jobs/Projects).
+/// On a dry run the renames are only printed
+pub async fn rename_label(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    from: &str,
+    to: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let labels = list_all_labels(hub).await?;
+    let from = find_existing_label(hub, &labels, from)
+        .await?
+        .name
+        .clone()
+        .unwrap_or_default();
+    let from = from.as_str();
+    if to == from || to.starts_with(&format!("{}/", from)) {
+        return Err(format!("Label {} can't be moved under itself", from).into());
+    }
+
+    // Every new name is checked before anything is renamed so a subtree is never left half moved
+    let mut renames: Vec<(String, String, String)> = Vec::new();
+    for label in subtree(&labels, from) {
+        let (Some(label_id), Some(label_name)) = (&label.id, &label.name) else {
+            continue;
+        };
+        let new_name = format!("{}{}", to, &label_name[from.len()..]);
+        if find_label(&labels, &new_name).is_some_and(|label| label.id != Some(label_id.clone())) {
+            return Err(format!(
+                "Can't rename {} to {} because that label already exists",
+                label_name, new_name
+            )
+            .into());
+        }
+        renames.push((label_id.clone(), label_name.clone(), new_name));
+    }
+
+    if dry_run {
+        for (_label_id, label_name, new_name) in renames {
+            println!("Dry run: would rename label {} -> {}", label_name, new_name);
+        }
+        return Ok(());
+    }
+
+    label_registry::invalidate();
+    for (label_id, _label_name, new_name) in renames {
+        let request = Label {
+            name: Some(new_name.clone()),
+            ..Default::default()
+        };
+        hub.users()
+            .labels_patch(request, "me", &label_id)
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+            .map_err(|e| {
+                format!(
+                    "Could not rename label to {}.\nError Received: {}",
+                    new_name, e
+                )
+            })?;
+        println!("Renamed label to {}", new_name);
+    }
+    Ok(())
+}
+
+/// Deletes a label, along with every label nested under it when recursive is set.
+/// Messages keep existing; they only lose the deleted labels. On a dry run the labels that would
+/// be deleted are only printed, children first
+pub async fn delete_label(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    name: &str,
+    recursive: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let labels = list_all_labels(hub).await?;
+    let label = find_existing_label(hub, &labels, name).await?;
+
+    let to_delete: Vec<&Label> = if recursive {
+        // Children go first so a parent is never deleted out from under them
+        let name = label.name.clone().unwrap_or_default();
+        subtree(&labels, &name).into_iter().rev().collect()
+    } else {
+        vec![label]
+    };
+
+    if dry_run {
+        for label in to_delete {
+            if let Some(label_name) = &label.name {
+                println!("Dry run: would delete label {}", label_name);
+            }
+        }
+        return Ok(());
+    }
+
+    label_registry::invalidate();
+
+    for label in to_delete {
+        let (Some(label_id), Some(label_name)) = (&label.id, &label.name) else {
+            continue;
+        };
+        hub.users()
+            .labels_delete("me", label_id)
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+            .map_err(|e| {
+                format!(
+                    "Could not delete label {}.\nError Received: {}",
+                    label_name, e
+                )
+            })?;
+        println!("Deleted label {}", label_name);
+    }
+    Ok(())
+}
+
+/// A level of the label tree: each child's name part and the labels nested under it
+#[derive(Default)]
+struct LabelNode {
+    children: BTreeMap<String, LabelNode>,
+}
+
+/// Prints the children of a tree node with box-drawing branches
+fn print_node(node: &LabelNode, prefix: &str) {
+    let last_index = node.children.len().saturating_sub(1);
+    for (index, (name, child)) in node.children.iter().enumerate() {
+        let (branch, child_prefix) = if index == last_index {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        println!("{}{}{}", prefix, branch, name);
+        print_node(child, &format!("{}{}", prefix, child_prefix));
+    }
+}
+
+/// Prints every user label as a tree, nesting labels under the `/`-separated parents in their names
+pub async fn print_label_tree(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let labels = list_all_labels(hub).await?;
+
+    let mut root = LabelNode::default();
+    for label in &labels {
+        if label.type_.as_deref() != Some("user") {
+            continue;
+        }
+        let Some(name) = &label.name else {
+            continue;
+        };
+        let mut node = &mut root;
+        for part in name.split('/') {
+            node = node.children.entry(part.to_string()).or_default();
+        }
+    }
+
+    if root.children.is_empty() {
+        println!("No user labels");
+        return Ok(());
+    }
+    print_node(&root, "");
+    Ok(())
+}
+
+/// A user label as written by labels export and read by labels import
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedLabel {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<LabelColor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label_list_visibility: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_list_visibility: Option<String>,
+}
+
+impl ExportedLabel {
+    /// The color as (background, text), which is what gets compared between accounts
+    fn color_pair(&self) -> (Option<&str>, Option<&str>) {
+        match &self.color {
+            Some(color) => (
+                color.background_color.as_deref(),
+                color.text_color.as_deref(),
+            ),
+            None => (None, None),
+        }
+    }
+}
+
+/// Writes every user label's name, color and visibility settings to a JSON file, sorted by name
+/// so parents come before the labels nested under them
+pub async fn export_labels(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    file: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut exported: Vec<ExportedLabel> = list_all_labels(hub)
+        .await?
+        .into_iter()
+        .filter(|label| label.type_.as_deref() == Some("user"))
+        .filter_map(|label| {
+            Some(ExportedLabel {
+                name: label.name?,
+                color: label.color,
+                label_list_visibility: label.label_list_visibility,
+                message_list_visibility: label.message_list_visibility,
+            })
+        })
+        .collect();
+    exported.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let exported_string = serde_json::to_string_pretty(&exported)?;
+    fs::write(file, exported_string)
+        .map_err(|e| format!("Unable to write file {}.\nError Received: {}", file, e))?;
+    println!("Exported {} labels to {}", exported.len(), file);
+    Ok(())
+}
+
+/// Creates the labels of an exported JSON file that are missing from the authenticated email.
+/// Labels that already exist are skipped; those whose color or visibility differ from the file, or
+/// whose name only differs in case, are reported as conflicts and left unchanged. On a dry run
+/// nothing is created
+pub async fn import_labels(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    file: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let imported_string = fs::read_to_string(file)
+        .map_err(|e| format!("Unable to read file {}.\nError Received: {}", file, e))?;
+    let mut imported: Vec<ExportedLabel> = serde_json::from_str(&imported_string)
+        .map_err(|e| format!("JSON was not well-formatted.\nError Received: {}", e))?;
+    // Parents are created before the labels nested under them
+    imported.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let labels = list_all_labels(hub).await?;
+    if !dry_run {
+        label_registry::invalidate();
+    }
+    let mut created: usize = 0;
+    let mut skipped: usize = 0;
+    let mut conflicts: Vec<String> = Vec::new();
+    for import in imported {
+        // Gmail label names are unique regardless of case
+        let existing = labels.iter().find(|label| {
+            label
+                .name
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(&import.name))
+        });
+        if let Some(existing) = existing {
+            let existing_name = existing.name.clone().unwrap_or_default();
+            let existing = ExportedLabel {
+                name: existing_name.clone(),
+                color: existing.color.clone(),
+                label_list_visibility: existing.label_list_visibility.clone(),
+                message_list_visibility: existing.message_list_visibility.clone(),
+            };
+            if existing_name != import.name {
+                conflicts.push(format!(
+                    "{}: exists as {} with different case",
+                    import.name, existing_name
+                ));
+            } else if existing.color_pair() != import.color_pair() {
+                conflicts.push(format!("{}: exists with a different color", import.name));
+            } else if existing.label_list_visibility != import.label_list_visibility
+                || existing.message_list_visibility != import.message_list_visibility
+            {
+                conflicts.push(format!(
+                    "{}: exists with different visibility settings",
+                    import.name
+                ));
+            } else {
+                skipped += 1;
+            }
+            continue;
+        }
+
+        if dry_run {
+            println!("Dry run: would create label {}", import.name);
+            created += 1;
+            continue;
+        }
+        let request = Label {
+            name: Some(import.name.clone()),
+            color: import.color,
+            label_list_visibility: import.label_list_visibility,
+            message_list_visibility: import.message_list_visibility,
+            ..Default::default()
+        };
+        match hub
+            .users()
+            .labels_create(request, "me")
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+        {
+            Ok(_) => {
+                println!("Created label {}", import.name);
+                created += 1;
+            }
+            Err(e) => println!(
+                "Could not create label {}.\nError Received: {}",
+                import.name, e
+            ),
+        }
+    }
+
+    if dry_run {
+        println!("\nWould create {} labels", created);
+    } else {
+        println!("\nCreated {} labels", created);
+    }
+    println!("Skipped {} labels that already exist", skipped);
+    if !conflicts.is_empty() {
+        println!("{} conflicts left unchanged:", conflicts.len());
+        for conflict in conflicts {
+            println!("    {}", conflict);
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,10 @@
 extern crate google_gmail1 as gmail1;
 
 use core::str;
-use gmail1::api::{Message, UserMessageListCall};
+use gmail1::api::{
+    BatchDeleteMessagesRequest, BatchModifyMessagesRequest, Message, ModifyMessageRequest,
+    ModifyThreadRequest, UserMessageListCall, UserThreadListCall,
+};
 use gmail1::hyper::client::HttpConnector;
 use gmail1::hyper_rustls::HttpsConnector;
 use gmail1::{hyper, hyper_rustls, oauth2, Gmail};
@@ -17,7 +20,10 @@ use std::path::Path;
 use std::sync::{Arc, Mutex};
 use tokio::sync::Mutex as tokio_mutex;
 
-use crate::cmd_args::{Filter, Send, SendInfo};
+use crate::cmd_args::{Filter, Send, SendInfo, TrashOptions};
+use crate::journal::{self, JournalOp, JournalTarget, LabelChanges};
+use crate::label_registry;
+use crate::protect;
 use crate::ringbuffer::MultiThreadedRingBuffer;
 
 /// Attempts to authenticate and connect to user's email; returns the connected client on success
@@ -84,6 +90,69 @@ pub async fn get_message(
     Ok(result.1)
 }
 
+/// Fetches only the labels, size, thread id and the given headers of a message from authenticated user's email
+/// This is much lighter than get_message when the body of the message isn't needed
+pub async fn get_message_metadata(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id: &str,
+    headers: &[&str],
+) -> Result<Message, Box<dyn std::error::Error>> {
+    let mut message_get = hub
+        .users()
+        .messages_get("me", msg_id)
+        .format("metadata")
+        .add_scope("https://mail.google.com/");
+
+    for header in headers {
+        message_get = message_get.add_metadata_headers(header);
+    }
+
+    let result = message_get.doit().await?;
+
+    Ok(result.1)
+}
+
+/// Returns the value of the first header in the message matching the header name (case insensitive)
+pub fn get_header(msg: &Message, header_name: &str) -> Option<String> {
+    msg.payload
+        .as_ref()?
+        .headers
+        .as_ref()?
+        .iter()
+        .find(|header| {
+            header
+                .name
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(header_name))
+        })
+        .and_then(|header| header.value.clone())
+}
+
+/// Pulls the bare lowercased address out of a From header like "Name <user@example.com>"
+pub fn sender_address(from: &str) -> String {
+    let address = match (from.rfind('<'), from.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &from[start + 1..end],
+        _ => from,
+    };
+    address.trim().to_lowercase()
+}
+
+/// Formats a size in bytes with the largest unit that keeps it at 1 or more (e.g. 1.5 MB)
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
 /// Send an email message to up to 100 users in to, cc, and bcc field respectively from a given mail sending host service using SMTP protocol.
 ///
 /// Code for building an email and sending mostly inspired by [Mailtrap](https://mailtrap.io/blog/rust-send-email/#How-to-send-an-email-with-attachments-in-Rust)
@@ -250,7 +319,12 @@ pub async fn send_message(
 }
 
 /// Parse query given all filled in field from Filter
-async fn query_parse(filter: Filter) -> Result<String, Box<dyn std::error::Error>> {
+pub async fn query_parse(filter: Filter) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(build_query(filter))
+}
+
+/// Builds the search query out of every filled in field of the Filter
+fn build_query(filter: Filter) -> String {
     let mut result = "".to_string();
 
     if let Some(words) = filter.words {
@@ -377,27 +451,37 @@ async fn query_parse(filter: Filter) -> Result<String, Box<dyn std::error::Error
         result.push_str(format!("smaller:{} ", smaller).as_str());
     }
 
-    Ok(result)
+    result
 }
 
-/// Parse query given in a text file
-async fn text_query_parse(text_file_path: String) -> Result<String, Box<dyn std::error::Error>> {
-    let result = read_to_string(text_file_path)?;
-    Ok(result)
+/// Builds the query of a filter, whether the query is given in a text file, json file or through the filter options
+pub fn filter_query(filter: &Filter) -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(text_file) = &filter.txt {
+        return Ok(read_to_string(text_file)
+            .map_err(|e| format!("Unable to read file {}.\nError Received: {}", text_file, e))?);
+    }
+    if let Some(json_file) = &filter.json {
+        let filter_string = fs::read_to_string(json_file)
+            .map_err(|e| format!("Unable to read file {}.\nError Received: {}", json_file, e))?;
+        let filter: Filter = serde_json::from_str(&filter_string)
+            .map_err(|e| format!("JSON was not well-formatted.\nError Received: {}", e))?;
+        return Ok(build_query(filter));
+    }
+    Ok(build_query(filter.clone()))
 }
 
-/// Parse query given in a json file
-async fn json_query_parse(json_file_path: String) -> Result<String, Box<dyn std::error::Error>> {
-    let filter_string = fs::read_to_string(json_file_path).expect("Unable to read file.");
-    let filter: Filter = serde_json::from_str(&filter_string).expect("JSON was not well-formatted");
-    query_parse(filter).await
+/// Parse query out of the filter, whether the query is given in a text file, json file or through the filter options
+async fn filter_query_parse(filter: Filter) -> Result<String, Box<dyn std::error::Error>> {
+    filter_query(&filter)
 }
 
 /// Return a Message List of all containing all Messages related to the page_token or query provided.
+/// Messages must also match extra_query (e.g. terms leaving out protected messages) if it isn't empty
 pub async fn list_messages<'a>(
     hub: &'a Gmail<HttpsConnector<HttpConnector>>,
     page_token: Option<&'a String>,
     filter: Option<Filter>,
+    extra_query: &str,
 ) -> UserMessageListCall<'a, HttpsConnector<HttpConnector>> {
     let mut result = hub.users().messages_list("me");
 
@@ -405,17 +489,50 @@ pub async fn list_messages<'a>(
         result = result.page_token(page_token);
     }
 
-    if let Some(filter) = filter {
-        let query_result;
+    let query_str = match filter {
+        Some(filter) => match filter_query_parse(filter).await {
+            // The filter's query is grouped so that an OR in it can't swallow the extra query
+            Ok(res) if !extra_query.is_empty() && !res.trim().is_empty() => {
+                Some(format!("({}) {}", res.trim(), extra_query))
+            }
+            Ok(_) if !extra_query.is_empty() => Some(extra_query.to_string()),
+            Ok(res) => Some(res),
+            Err(e) => {
+                println!(
+                    "The query search does not contain proper query information.\n Error received: {}",
+                    e
+                );
+                return result.max_results(0).add_scope("https://mail.google.com/");
+            }
+        },
+        None if !extra_query.is_empty() => Some(extra_query.to_string()),
+        None => None,
+    };
 
-        if let Some(text_file) = filter.txt.clone() {
-            query_result = text_query_parse(text_file).await;
-        } else if let Some(json_file) = filter.json.clone() {
-            query_result = json_query_parse(json_file).await;
-        } else {
-            query_result = query_parse(filter.clone()).await;
-        }
-        match query_result {
+    // query up search with given user inputs from either text, json, or manual querying.
+    if let Some(query_str) = query_str {
+        result = result.q(&query_str).max_results(500);
+    }
+
+    result = result.add_scope("https://mail.google.com/");
+
+    result
+}
+
+/// Return a Thread List of all containing all Threads related to the page_token or query provided.
+pub async fn list_threads<'a>(
+    hub: &'a Gmail<HttpsConnector<HttpConnector>>,
+    page_token: Option<&'a String>,
+    filter: Option<Filter>,
+) -> UserThreadListCall<'a, HttpsConnector<HttpConnector>> {
+    let mut result = hub.users().threads_list("me");
+
+    if let Some(page_token) = page_token {
+        result = result.page_token(page_token);
+    }
+
+    if let Some(filter) = filter {
+        match filter_query_parse(filter).await {
             Ok(res) => {
                 let query_str = &res;
                 // query up search with given user inputs from either text, json, or manual querying.
@@ -437,29 +554,40 @@ pub async fn list_messages<'a>(
 }
 
 /// Inserts message ids into Arc<tokio_mutex<BTreeSet>> with given label ids or filters
+/// Messages must carry every label id in label_ids to be inserted
 pub async fn get_msg_ids_from_messages(
     hub: &Gmail<HttpsConnector<HttpConnector>>,
-    label_id: Option<&str>,
+    label_ids: &[&str],
     filter: Option<Filter>,
     msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
 ) {
+    // Displays the error if an error occurred in getting message list
+    if let Err(e) = get_msg_ids_from_query(hub, label_ids, filter, "", msg_id_bts).await {
+        println!("{}", e);
+    }
+}
+
+/// Inserts message ids into Arc<tokio_mutex<BTreeSet>> with given label ids or filters
+/// Messages must carry every label id in label_ids and also match extra_query to be inserted.
+/// Returns the error if a page of the message list couldn't be retrieved
+pub async fn get_msg_ids_from_query(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_ids: &[&str],
+    filter: Option<Filter>,
+    extra_query: &str,
+    msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut fetch_emails: bool = true;
     let mut message_list: UserMessageListCall<HttpsConnector<HttpConnector>> =
-        list_messages(hub, None, filter.clone()).await;
+        list_messages(hub, None, filter.clone(), extra_query).await;
 
-    if let Some(label_id) = label_id {
-        message_list = message_list.add_label_ids(label_id);
-    }
+    message_list = with_label_ids(message_list, label_ids);
 
     let mut result = message_list.doit().await;
 
     while fetch_emails {
-        // Displays whether the result retrieved a message list or an error occurred in getting message list
         let messages = match result {
-            Err(e) => {
-                println!("{}", e);
-                return;
-            }
+            Err(e) => return Err(e.into()),
             Ok(ref res) => res.1.clone(),
         };
 
@@ -473,17 +601,249 @@ pub async fn get_msg_ids_from_messages(
         // Keep retrieving rest of the message ids so long as there is a next page
         if let Some(page_token) = &messages.next_page_token {
             let mut message_list: UserMessageListCall<HttpsConnector<HttpConnector>> =
-                list_messages(hub, Some(page_token), filter.clone()).await;
+                list_messages(hub, Some(page_token), filter.clone(), extra_query).await;
 
-            if let Some(label_id) = label_id {
-                message_list = message_list.add_label_ids(label_id);
-            }
+            message_list = with_label_ids(message_list, label_ids);
 
             result = message_list.doit().await;
         } else {
             fetch_emails = false;
         }
     }
+    Ok(())
+}
+
+/// Restricts a message list call to messages carrying all of the given label ids
+/// Gmail leaves out trashed and spam messages unless asked for them, so they're included
+/// whenever TRASH or SPAM is one of the label ids
+fn with_label_ids<'a>(
+    mut message_list: UserMessageListCall<'a, HttpsConnector<HttpConnector>>,
+    label_ids: &[&str],
+) -> UserMessageListCall<'a, HttpsConnector<HttpConnector>> {
+    for label_id in label_ids {
+        message_list = message_list.add_label_ids(label_id);
+    }
+
+    if label_ids.contains(&"TRASH") || label_ids.contains(&"SPAM") {
+        message_list = message_list.include_spam_trash(true);
+    }
+
+    message_list
+}
+
+/// Inserts thread ids into Arc<tokio_mutex<BTreeSet>> with given label ids or filters
+/// Threads must carry every label id in label_ids to be inserted. A thread showing up on
+/// several pages is only inserted once
+pub async fn get_thread_ids_from_threads(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_ids: &[&str],
+    filter: Option<Filter>,
+    thread_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) {
+    // Displays the error if an error occurred in getting thread list
+    if let Err(e) = get_thread_ids_from_query(hub, label_ids, filter, thread_id_bts).await {
+        println!("{}", e);
+    }
+}
+
+/// Inserts thread ids into Arc<tokio_mutex<BTreeSet>> with given label ids or filters
+/// Threads must carry every label id in label_ids to be inserted.
+/// Returns the error if a page of the thread list couldn't be retrieved
+async fn get_thread_ids_from_query(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_ids: &[&str],
+    filter: Option<Filter>,
+    thread_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut thread_list: UserThreadListCall<HttpsConnector<HttpConnector>> =
+            list_threads(hub, page_token.as_ref(), filter.clone()).await;
+
+        for label_id in label_ids {
+            thread_list = thread_list.add_label_ids(label_id);
+        }
+
+        if label_ids.contains(&"TRASH") || label_ids.contains(&"SPAM") {
+            thread_list = thread_list.include_spam_trash(true);
+        }
+
+        let (_res, threads) = thread_list.doit().await?;
+
+        if let Some(gmail_threads) = threads.threads {
+            let mut thread_id_bts_lock = thread_id_bts.lock().await;
+            for thread in gmail_threads {
+                if let Some(thread_id) = thread.id {
+                    thread_id_bts_lock.insert(Some(thread_id));
+                }
+            }
+        }
+
+        // Keep retrieving rest of the thread ids so long as there is a next page
+        match threads.next_page_token {
+            Some(next_page_token) => page_token = Some(next_page_token),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// Works out which of the selected messages (or threads) each label id added or removed actually
+/// changes on. Selections by labels or filter are listed again with each label id also required,
+/// so the cost grows with the selection rather than the mailbox; selections by message ids use
+/// the label ids the messages (or their threads) carry. A thread counts as carrying a label id
+/// when any of its messages does
+pub async fn label_changes(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    trash_opt: &TrashOptions,
+    required_label_ids: &[&str],
+    selected: &BTreeSet<String>,
+    add_label_ids: &[String],
+    remove_label_ids: &[String],
+    by_threads: bool,
+) -> LabelChanges {
+    let passes: Vec<Vec<String>>;
+    let filter: Option<Filter>;
+    match trash_opt {
+        TrashOptions::ByMsgIds(_msg_ids) => {
+            let carried = carried_label_ids(hub, selected, by_threads).await;
+            return LabelChanges::from_carried(&carried, add_label_ids, remove_label_ids);
+        }
+        TrashOptions::ByLabels(labels) => {
+            let mut label_passes = Vec::new();
+            for label in &labels.labels {
+                // Labels that don't exist picked nothing out, so there's nothing to list again
+                if let Ok(label_id) = get_label_id(hub, label).await {
+                    let mut label_ids = vec![label_id];
+                    label_ids.extend(required_label_ids.iter().map(|id| id.to_string()));
+                    label_passes.push(label_ids);
+                }
+            }
+            passes = label_passes;
+            filter = None;
+        }
+        TrashOptions::ByFilter(by_filter) => {
+            passes = vec![required_label_ids.iter().map(|id| id.to_string()).collect()];
+            filter = Some(*by_filter.clone());
+        }
+    }
+
+    let carrying = selection_carrying(
+        hub,
+        selected,
+        &passes,
+        filter,
+        add_label_ids.iter().chain(remove_label_ids),
+        by_threads,
+    )
+    .await;
+    LabelChanges::new(selected, add_label_ids, remove_label_ids, &carrying)
+}
+
+/// Works out which of the selected ids carry each of the given label ids by going through the
+/// selection's list passes (each a set of required label ids, along with the filter) again with
+/// the label id also required. A label id whose lists can't be retrieved is left out, so it's
+/// taken to change on every id
+pub async fn selection_carrying<'a>(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    selected: &BTreeSet<String>,
+    passes: &[Vec<String>],
+    filter: Option<Filter>,
+    label_ids: impl Iterator<Item = &'a String>,
+    by_threads: bool,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut carrying: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    if selected.is_empty() {
+        return carrying;
+    }
+
+    'labels: for label_id in label_ids {
+        let carrying_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+            Arc::new(tokio_mutex::new(BTreeSet::new()));
+        for pass in passes {
+            let mut pass_label_ids: Vec<&str> = pass.iter().map(|id| id.as_str()).collect();
+            pass_label_ids.push(label_id);
+            let result = if by_threads {
+                get_thread_ids_from_query(
+                    hub,
+                    &pass_label_ids,
+                    filter.clone(),
+                    carrying_bts.clone(),
+                )
+                .await
+            } else {
+                get_msg_ids_from_query(
+                    hub,
+                    &pass_label_ids,
+                    filter.clone(),
+                    "",
+                    carrying_bts.clone(),
+                )
+                .await
+            };
+            if let Err(e) = result {
+                println!(
+                    "Could not list what carries label id {}, journaling it as changed on all of them.\nError Received: {}",
+                    label_id, e
+                );
+                continue 'labels;
+            }
+        }
+        let carrying_bts_lock = carrying_bts.lock().await;
+        let carrying_ids = carrying_bts_lock
+            .iter()
+            .flatten()
+            .filter(|id| selected.contains(*id))
+            .cloned()
+            .collect();
+        carrying.insert(label_id.clone(), carrying_ids);
+    }
+    carrying
+}
+
+/// Gets the label ids each of the given messages (or threads) carries, a thread carrying every
+/// label id of its messages. Ids that can't be retrieved are left out
+async fn carried_label_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    ids: &BTreeSet<String>,
+    by_threads: bool,
+) -> BTreeMap<String, Vec<String>> {
+    let mut carried: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for id in ids {
+        let label_ids: Result<Vec<String>, Box<dyn std::error::Error>> = if by_threads {
+            hub.users()
+                .threads_get("me", id)
+                .format("minimal")
+                .add_scope("https://mail.google.com/")
+                .doit()
+                .await
+                .map(|(_res, thread)| {
+                    let thread_label_ids: BTreeSet<String> = thread
+                        .messages
+                        .unwrap_or_default()
+                        .into_iter()
+                        .flat_map(|msg| msg.label_ids.unwrap_or_default())
+                        .collect();
+                    thread_label_ids.into_iter().collect()
+                })
+                .map_err(|e| e.into())
+        } else {
+            get_message_metadata(hub, id, &[])
+                .await
+                .map(|msg| msg.label_ids.unwrap_or_default())
+        };
+        match label_ids {
+            Ok(label_ids) => {
+                carried.insert(id.clone(), label_ids);
+            }
+            Err(e) => println!(
+                "Could not get the labels of {}, leaving it out.\nError Received: {}",
+                id, e
+            ),
+        }
+    }
+    carried
 }
 
 /// Return a BTreeMap of label names and ids within user's email
@@ -505,40 +865,52 @@ pub async fn list_labels(
     Ok(label_map)
 }
 
-/// Retrieves label id given label name
+/// Retrieves label id given label name (in any case) or label id, from the labels loaded once per
+/// session. Returns a message naming the closest existing labels as the error if it doesn't exist
 pub async fn get_label_id(
     hub: &Gmail<HttpsConnector<HttpConnector>>,
-    label_name: &String,
-) -> Option<String> {
-    let label_id = list_labels(hub).await;
-
-    match label_id {
-        Err(e) => {
-            println!("Labels list unsuccessfully received.\nError Received {}", e);
-            None
-        }
-        Ok(res) => res.get(label_name).cloned(),
-    }
+    label_name: &str,
+) -> Result<String, String> {
+    label_registry::label_id(hub, label_name).await
 }
 
 /// Checks if a label name provided by user exists and passes the label id of the label name
 /// to retrieve all messages and add it to the BTreeSet
+/// Messages must also carry every label id in required_label_ids to be added
 pub async fn add_msg_ids_from_labels(
     hub: &Gmail<HttpsConnector<HttpConnector>>,
     label_names: Vec<String>,
+    required_label_ids: &[&str],
     msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
 ) {
     for label in label_names {
-        let label_id = get_label_id(hub, &label).await;
+        let label_id = match get_label_id(hub, &label).await {
+            Ok(label_id) => label_id,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
 
-        if label_id.is_none() {
-            println!("{} is a nonexistent label name", label);
-            continue;
-        }
-        get_msg_ids_from_messages(hub, label_id.as_deref(), None, msg_id_bts.clone()).await;
+        let mut label_ids = vec![label_id.as_str()];
+        label_ids.extend_from_slice(required_label_ids);
+        get_msg_ids_from_messages(hub, &label_ids, None, msg_id_bts.clone()).await;
     }
 }
 
+/// Retrieves the label id of every given label name
+/// Returns the error of the first label name that doesn't exist
+pub async fn get_label_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_names: &[String],
+) -> Result<Vec<String>, String> {
+    let mut label_ids = Vec::with_capacity(label_names.len());
+    for label in label_names {
+        label_ids.push(get_label_id(hub, label).await?);
+    }
+    Ok(label_ids)
+}
+
 /// Add msgs ids to the BTreeSet from provided message ids
 pub async fn add_msg_ids_from_ids(
     hub: &Gmail<HttpsConnector<HttpConnector>>,
@@ -557,27 +929,521 @@ pub async fn add_msg_ids_from_ids(
     }
 }
 
-/// Dequerer threads in the trash command utilize this method to grab the msg id
-/// from the ring buffer and trash it
+/// Adds the message ids picked out by the by-msg-ids, by-labels or by-filter selector to the BTreeSet
+/// Messages found by labels or filter must also carry every label id in required_label_ids
+pub async fn add_msg_ids_from_opt(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    trash_opt: TrashOptions,
+    required_label_ids: &[&str],
+    msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) {
+    match trash_opt {
+        TrashOptions::ByMsgIds(msg_ids) => {
+            add_msg_ids_from_ids(hub, msg_ids.msg_ids, msg_id_bts).await;
+        }
+        TrashOptions::ByLabels(labels) => {
+            add_msg_ids_from_labels(hub, labels.labels, required_label_ids, msg_id_bts).await;
+        }
+        TrashOptions::ByFilter(filter) => {
+            get_msg_ids_from_messages(hub, required_label_ids, Some(*filter), msg_id_bts).await;
+        }
+    }
+}
+
+/// The most message ids the Gmail API accepts in a single batchModify or batchDelete request
+pub const BATCH_SIZE: usize = 1000;
+
+/// Adds and removes label ids on up to BATCH_SIZE messages through a single users.messages.batchModify call
+pub async fn batch_modify_msgs(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_ids: &[String],
+    add_label_ids: &[&str],
+    remove_label_ids: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let request = BatchModifyMessagesRequest {
+        ids: Some(msg_ids.to_vec()),
+        add_label_ids: Some(add_label_ids.iter().map(|id| id.to_string()).collect()),
+        remove_label_ids: Some(remove_label_ids.iter().map(|id| id.to_string()).collect()),
+    };
+
+    hub.users()
+        .messages_batch_modify(request, "me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await?;
+
+    Ok(())
+}
+
+/// Trashes a chunk of messages by giving them the TRASH label in one batchModify call.
+/// Protected messages have to be left out before the msg ids get here.
+/// If the batch call fails, each message is trashed one at a time instead.
+/// Returns the number of messages that were actually trashed
+async fn trash_batch(hub: &Gmail<HttpsConnector<HttpConnector>>, msg_ids: &[String]) -> usize {
+    match batch_modify_msgs(hub, msg_ids, &["TRASH"], &[]).await {
+        Ok(()) => {
+            journal::record(JournalTarget::Messages, JournalOp::Trash, msg_ids);
+            return msg_ids.len();
+        }
+        Err(e) => println!(
+            "Could not batch trash {} messages, trashing them one at a time.\nError Received: {}",
+            msg_ids.len(),
+            e
+        ),
+    };
+
+    let mut trashed: Vec<String> = Vec::with_capacity(msg_ids.len());
+    for msg_id in msg_ids {
+        let result = hub.users().messages_trash("me", msg_id).doit().await;
+        // Displays whether the message was trashed or something failed
+        match result {
+            Ok(_res) => trashed.push(msg_id.clone()),
+            Err(e) => println!(
+                "Could not trash message with id {}.\nError Received: {}",
+                msg_id, e
+            ),
+        };
+    }
+    journal::record(JournalTarget::Messages, JournalOp::Trash, &trashed);
+    trashed.len()
+}
+
+/// Dequeues msg ids from the ring buffer into msg_ids until it holds BATCH_SIZE ids or the
+/// ring buffer hands out a None. Returns false once the None is reached, meaning no more msg ids are coming
+async fn dequeue_batch(
+    msg_id_rb: &MultiThreadedRingBuffer<String>,
+    msg_ids: &mut Vec<String>,
+) -> bool {
+    while msg_ids.len() < BATCH_SIZE {
+        match msg_id_rb.dequeue().await {
+            Some(msg_id) => msg_ids.push(msg_id),
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Adds the ids of the threads that the messages picked out by the by-msg-ids, by-labels or
+/// by-filter selector belong to into the BTreeSet.
+/// Threads found by labels or filter must also carry every label id in required_label_ids
+pub async fn add_thread_ids_from_opt(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    trash_opt: TrashOptions,
+    required_label_ids: &[&str],
+    thread_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) {
+    match trash_opt {
+        TrashOptions::ByMsgIds(msg_ids) => {
+            for msg_id in msg_ids.msg_ids {
+                match get_message_metadata(hub, &msg_id, &[]).await {
+                    Ok(Message {
+                        thread_id: Some(thread_id),
+                        ..
+                    }) => {
+                        let mut thread_id_bts_lock = thread_id_bts.lock().await;
+                        thread_id_bts_lock.insert(Some(thread_id));
+                    }
+                    _ => println!("{} is a nonexistent message id", msg_id),
+                }
+            }
+        }
+        TrashOptions::ByLabels(labels) => {
+            for label in labels.labels {
+                let label_id = match get_label_id(hub, &label).await {
+                    Ok(label_id) => label_id,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                let mut label_ids = vec![label_id.as_str()];
+                label_ids.extend_from_slice(required_label_ids);
+                get_thread_ids_from_threads(hub, &label_ids, None, thread_id_bts.clone()).await;
+            }
+        }
+        TrashOptions::ByFilter(filter) => {
+            get_thread_ids_from_threads(hub, required_label_ids, Some(*filter), thread_id_bts)
+                .await;
+        }
+    }
+}
+
+/// Trashes the given messages in chunks of up to BATCH_SIZE messages without going through the ring buffer.
+/// Returns the number of messages that were successfully trashed
+pub async fn trash_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_ids: &[String],
+) -> usize {
+    let mut counter: usize = 0;
+    for chunk in msg_ids.chunks(BATCH_SIZE) {
+        counter += trash_batch(hub, chunk).await;
+    }
+    counter
+}
+
+/// Dequerer threads in the trash command utilize this method to grab msg ids
+/// from the ring buffer and trash them in chunks of up to BATCH_SIZE messages.
+/// Returns the number of messages that were successfully trashed
 pub async fn trash_msgs(
     hub: &Gmail<HttpsConnector<HttpConnector>>,
     msg_id_rb: &MultiThreadedRingBuffer<String>,
+) -> usize {
+    let mut counter: usize = 0;
+    let mut msg_ids: Vec<String> = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        let more_msg_ids = dequeue_batch(msg_id_rb, &mut msg_ids).await;
+        if !msg_ids.is_empty() {
+            counter += trash_batch(hub, &msg_ids).await;
+            msg_ids.clear();
+        }
+        if !more_msg_ids {
+            break;
+        }
+    }
+    counter
+}
+
+/// Makes the label changes on a chunk of messages in one batchModify call.
+/// If the batch call fails, each message is modified one at a time instead.
+/// Returns the number of messages that were actually modified
+async fn modify_batch(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_ids: &[String],
+    changes: &LabelChanges,
+) -> usize {
+    let add_label_ids = changes.add_label_ids();
+    let remove_label_ids = changes.remove_label_ids();
+    let add_label_id_strs: Vec<&str> = add_label_ids.iter().map(String::as_str).collect();
+    let remove_label_id_strs: Vec<&str> = remove_label_ids.iter().map(String::as_str).collect();
+
+    match batch_modify_msgs(hub, msg_ids, &add_label_id_strs, &remove_label_id_strs).await {
+        Ok(()) => {
+            changes.record(JournalTarget::Messages, msg_ids);
+            return msg_ids.len();
+        }
+        Err(e) => println!(
+            "Could not batch modify {} messages, modifying them one at a time.\nError Received: {}",
+            msg_ids.len(),
+            e
+        ),
+    };
+
+    let mut modified: Vec<String> = Vec::with_capacity(msg_ids.len());
+    for msg_id in msg_ids {
+        let request = ModifyMessageRequest {
+            add_label_ids: Some(add_label_ids.clone()),
+            remove_label_ids: Some(remove_label_ids.clone()),
+        };
+        let result = hub
+            .users()
+            .messages_modify(request, "me", msg_id)
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await;
+        // Displays whether the message was modified or something failed
+        match result {
+            Ok(_res) => modified.push(msg_id.clone()),
+            Err(e) => println!(
+                "Could not modify message with id {}.\nError Received: {}",
+                msg_id, e
+            ),
+        };
+    }
+    changes.record(JournalTarget::Messages, &modified);
+    modified.len()
+}
+
+/// Makes the label changes on the given messages in chunks of up to BATCH_SIZE
+/// messages without going through the ring buffer.
+/// Returns the number of messages that were successfully modified
+pub async fn modify_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_ids: &[String],
+    changes: &LabelChanges,
+) -> usize {
+    let mut counter: usize = 0;
+    for chunk in msg_ids.chunks(BATCH_SIZE) {
+        counter += modify_batch(hub, chunk, changes).await;
+    }
+    counter
+}
+
+/// Dequerer threads in the label command utilize this method to grab msg ids from the ring buffer
+/// and make the label changes on them in chunks of up to BATCH_SIZE messages.
+/// Returns the number of messages that were successfully modified
+pub async fn modify_msgs(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id_rb: &MultiThreadedRingBuffer<String>,
+    changes: Arc<LabelChanges>,
+) -> usize {
+    let mut counter: usize = 0;
+    let mut msg_ids: Vec<String> = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        let more_msg_ids = dequeue_batch(msg_id_rb, &mut msg_ids).await;
+        if !msg_ids.is_empty() {
+            counter += modify_batch(hub, &msg_ids, &changes).await;
+            msg_ids.clear();
+        }
+        if !more_msg_ids {
+            break;
+        }
+    }
+    counter
+}
+
+/// Trashes every message in a thread unless one of them is protected.
+/// Returns whether the thread was trashed
+async fn trash_thread(hub: &Gmail<HttpsConnector<HttpConnector>>, thread_id: &str) -> bool {
+    if protect::is_thread_protected(hub, thread_id).await {
+        return false;
+    }
+
+    let result = hub
+        .users()
+        .threads_trash("me", thread_id)
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await;
+    // Displays whether the thread was trashed or something failed
+    match result {
+        Ok(_res) => {
+            journal::record(
+                JournalTarget::Threads,
+                JournalOp::Trash,
+                &[thread_id.to_string()],
+            );
+            true
+        }
+        Err(e) => {
+            println!(
+                "Could not trash thread with id {}.\nError Received: {}",
+                thread_id, e
+            );
+            false
+        }
+    }
+}
+
+/// Untrashes every message in a thread. Returns whether the thread was untrashed
+async fn untrash_thread(hub: &Gmail<HttpsConnector<HttpConnector>>, thread_id: &str) -> bool {
+    let result = hub
+        .users()
+        .threads_untrash("me", thread_id)
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await;
+    // Displays whether the thread was untrashed or something failed
+    match result {
+        Ok(_res) => {
+            journal::record(
+                JournalTarget::Threads,
+                JournalOp::Untrash,
+                &[thread_id.to_string()],
+            );
+            true
+        }
+        Err(e) => {
+            println!(
+                "Could not untrash thread with id {}.\nError Received: {}",
+                thread_id, e
+            );
+            false
+        }
+    }
+}
+
+/// Makes the label changes on every message in a thread. Returns whether the thread was modified
+async fn modify_thread(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_id: &str,
+    changes: &LabelChanges,
+) -> bool {
+    let request = ModifyThreadRequest {
+        add_label_ids: Some(changes.add_label_ids()),
+        remove_label_ids: Some(changes.remove_label_ids()),
+    };
+    let result = hub
+        .users()
+        .threads_modify(request, "me", thread_id)
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await;
+    // Displays whether the thread was modified or something failed
+    match result {
+        Ok(_res) => {
+            changes.record(JournalTarget::Threads, &[thread_id.to_string()]);
+            true
+        }
+        Err(e) => {
+            println!(
+                "Could not modify thread with id {}.\nError Received: {}",
+                thread_id, e
+            );
+            false
+        }
+    }
+}
+
+/// Dequerer threads in the trash command utilize this method in threads mode to grab
+/// thread ids from the ring buffer and trash every message in those threads.
+/// Returns the number of threads that were successfully trashed
+pub async fn trash_threads(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_id_rb: &MultiThreadedRingBuffer<String>,
+) -> usize {
+    let mut counter: usize = 0;
+    while let Some(thread_id) = thread_id_rb.dequeue().await {
+        if trash_thread(hub, &thread_id).await {
+            counter += 1;
+        }
+    }
+    counter
+}
+
+/// Dequerer threads in the label commands utilize this method in threads mode to grab thread ids
+/// from the ring buffer and make the label changes on every message in those threads.
+/// Returns the number of threads that were successfully modified
+pub async fn modify_threads(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_id_rb: &MultiThreadedRingBuffer<String>,
+    changes: Arc<LabelChanges>,
+) -> usize {
+    let mut counter: usize = 0;
+    while let Some(thread_id) = thread_id_rb.dequeue().await {
+        if modify_thread(hub, &thread_id, &changes).await {
+            counter += 1;
+        }
+    }
+    counter
+}
+
+/// Trashes the given threads one at a time without going through the ring buffer.
+/// Returns the number of threads that were successfully trashed
+pub async fn trash_thread_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_ids: &[String],
+) -> usize {
+    let mut counter: usize = 0;
+    for thread_id in thread_ids {
+        if trash_thread(hub, thread_id).await {
+            counter += 1;
+        }
+    }
+    counter
+}
+
+/// Untrashes the given threads one at a time without going through the ring buffer.
+/// Returns the number of threads that were successfully untrashed
+pub async fn untrash_thread_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_ids: &[String],
+) -> usize {
+    let mut counter: usize = 0;
+    for thread_id in thread_ids {
+        if untrash_thread(hub, thread_id).await {
+            counter += 1;
+        }
+    }
+    counter
+}
+
+/// Makes the label changes on the given threads one at a time without going
+/// through the ring buffer. Returns the number of threads that were successfully modified
+pub async fn modify_thread_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_ids: &[String],
+    changes: &LabelChanges,
+) -> usize {
+    let mut counter: usize = 0;
+    for thread_id in thread_ids {
+        if modify_thread(hub, thread_id, changes).await {
+            counter += 1;
+        }
+    }
+    counter
+}
+
+/// Permanently deletes the given messages in chunks of up to BATCH_SIZE through users.messages.batchDelete.
+/// Returns the number of messages that were deleted
+pub async fn delete_msgs(hub: &Gmail<HttpsConnector<HttpConnector>>, msg_ids: &[String]) -> usize {
+    let mut counter: usize = 0;
+    for chunk in msg_ids.chunks(BATCH_SIZE) {
+        let request = BatchDeleteMessagesRequest {
+            ids: Some(chunk.to_vec()),
+        };
+        let result = hub
+            .users()
+            .messages_batch_delete(request, "me")
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await;
+        // Displays whether the chunk was deleted or something failed
+        match result {
+            Ok(_res) => counter += chunk.len(),
+            Err(e) => println!(
+                "Could not delete {} messages starting from id {}.\nError Received: {}",
+                chunk.len(),
+                chunk[0],
+                e
+            ),
+        };
+    }
+    counter
+}
+
+/// Untrashes a single message. Returns whether the message was untrashed
+async fn untrash_msg(hub: &Gmail<HttpsConnector<HttpConnector>>, msg_id: &str) -> bool {
+    let result = hub.users().messages_untrash("me", msg_id).doit().await;
+    // Displays whether the message was untrashed or something failed
+    match result {
+        Ok(_res) => {
+            journal::record(
+                JournalTarget::Messages,
+                JournalOp::Untrash,
+                &[msg_id.to_string()],
+            );
+            true
+        }
+        Err(e) => {
+            println!(
+                "Could not untrash message with id {}.\nError Received: {}",
+                msg_id, e
+            );
+            false
+        }
+    }
+}
+
+/// Untrashes the given messages one at a time without going through the ring buffer.
+/// Returns the number of messages that were successfully untrashed
+pub async fn untrash_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_ids: &[String],
+) -> usize {
+    let mut counter: usize = 0;
+    for msg_id in msg_ids {
+        if untrash_msg(hub, msg_id).await {
+            counter += 1;
+        }
+    }
+    counter
+}
+
+/// Dequerer threads in the untrash command utilize this method to grab the msg id
+/// from the ring buffer and untrash it.
+/// Returns the number of messages that were successfully untrashed
+pub async fn untrash_msgs(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id_rb: &MultiThreadedRingBuffer<String>,
 ) -> usize {
     let mut counter: usize = 0;
     loop {
         let msg_id = msg_id_rb.dequeue().await;
         match msg_id {
             Some(msg_id) => {
-                counter += 1;
-                let result = hub.users().messages_trash("me", &msg_id).doit().await;
-                // Displays whether the message was trashed or something failed
-                match result {
-                    Ok(_res) => {}
-                    Err(e) => println!(
-                        "Could not trash message with id {}.\nError Received: {}",
-                        msg_id, e
-                    ),
-                };
+                if untrash_msg(hub, &msg_id).await {
+                    counter += 1;
+                }
             }
             None => {
                 break;
@@ -598,15 +1464,18 @@ pub async fn add_msgs(
     loop {
         // Lock the bts so that you can read popped item and remove it from the bts (read/write lock)
         let mut msg_id_bts_lock = msg_ids.lock().await;
-        match msg_id_bts_lock.pop_first() {
+        // None sorts before every msg id, so popping from the back only reaches the None once
+        // every msg id in the bts has been enqueued
+        match msg_id_bts_lock.pop_last() {
             Some(msg_id) => {
                 // enqueue the msg_id
                 if let Some(msg_id) = msg_id {
                     counter += 1;
                     msg_id_rb.enqueue(msg_id).await;
                 }
-                // item is None here
+                // item is None here; put it back so the other enquerer threads see it too
                 else {
+                    msg_id_bts_lock.insert(None);
                     break;
                 }
             }
@@ -619,6 +1488,170 @@ pub async fn add_msgs(
     counter
 }
 
+/// Appends text to the given output txt file, creating the file if it doesn't exist yet
+pub fn append_to_output(output_file: &str, text: &str) {
+    let mut output_file = output_file.to_string();
+    output_file.push_str(".txt");
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_file)
+        .expect("Could not open file");
+
+    file.write_all(text.as_bytes())
+        .expect("Couldn't write to file.");
+}
+
+/// Builds the dry run listing of a message: its id, sender, subject and date
+pub async fn msg_listing(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let msg = get_message_metadata(hub, msg_id, &["From", "Subject", "Date"]).await?;
+
+    Ok(format!(
+        "Message ID: {}\nFrom: {}\nSubject: {}\nDate: {}\n\n",
+        msg_id,
+        get_header(&msg, "From").unwrap_or("Not found".to_string()),
+        get_header(&msg, "Subject").unwrap_or("Not found".to_string()),
+        get_header(&msg, "Date").unwrap_or("Not found".to_string()),
+    ))
+}
+
+/// Lists the id, sender, subject and date of every given message one after another,
+/// either to stdout or appended to the output txt file if one is given
+pub async fn preview_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_ids: &[String],
+    output_file: &Option<String>,
+) {
+    for msg_id in msg_ids {
+        match msg_listing(hub, msg_id).await {
+            Ok(listing) => match output_file {
+                Some(output_file) => append_to_output(output_file, &listing),
+                None => print!("{}", listing),
+            },
+            Err(e) => println!(
+                "Could not find message with id {}.\nError Received: {}",
+                msg_id, e
+            ),
+        }
+    }
+}
+
+/// Dequerer threads in dry runs utilize this method to grab the msg id from the ring buffer
+/// and list the id, sender, subject and date of the message that would have been affected.
+/// The listing goes to stdout, or is appended to the output txt file if one is given
+pub async fn preview_msgs(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id_rb: &MultiThreadedRingBuffer<String>,
+    output_file: Option<String>,
+    file_lock: Arc<Mutex<i32>>,
+) -> usize {
+    let mut counter: usize = 0;
+    loop {
+        let msg_id = msg_id_rb.dequeue().await;
+        match msg_id {
+            Some(msg_id) => {
+                counter += 1;
+                let listing = match msg_listing(hub, &msg_id).await {
+                    Ok(listing) => listing,
+                    Err(e) => {
+                        println!(
+                            "Could not find message with id {}.\nError Received: {}",
+                            msg_id, e
+                        );
+                        continue;
+                    }
+                };
+
+                // Lock so that listings from different threads don't interleave
+                let file_lock = file_lock.lock().unwrap();
+                match &output_file {
+                    Some(output_file) => append_to_output(output_file, &listing),
+                    None => print!("{}", listing),
+                }
+                drop(file_lock)
+            }
+            None => {
+                break;
+            }
+        }
+    }
+    counter
+}
+
+/// Dequerer threads in dry runs utilize this method in threads mode to grab the thread id from the
+/// ring buffer and list the id and message count of the thread that would have been affected,
+/// along with the sender, subject and date of its first message. With skip_protected set (when
+/// previewing trash), protected threads are skipped the same way trash skips them.
+/// The listing goes to stdout, or is appended to the output txt file if one is given
+pub async fn preview_threads(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_id_rb: &MultiThreadedRingBuffer<String>,
+    output_file: Option<String>,
+    file_lock: Arc<Mutex<i32>>,
+    skip_protected: bool,
+) -> usize {
+    let mut counter: usize = 0;
+    while let Some(thread_id) = thread_id_rb.dequeue().await {
+        if skip_protected && protect::is_thread_protected(hub, &thread_id).await {
+            continue;
+        }
+        counter += 1;
+        let result = hub
+            .users()
+            .threads_get("me", &thread_id)
+            .format("metadata")
+            .add_metadata_headers("From")
+            .add_metadata_headers("Subject")
+            .add_metadata_headers("Date")
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await;
+        let messages = match result {
+            Ok(res) => res.1.messages.unwrap_or_default(),
+            Err(e) => {
+                println!(
+                    "Could not find thread with id {}.\nError Received: {}",
+                    thread_id, e
+                );
+                continue;
+            }
+        };
+
+        let (mut from, mut subject, mut date) = (
+            "Not found".to_string(),
+            "Not found".to_string(),
+            "Not found".to_string(),
+        );
+        if let Some(first_msg) = messages.first() {
+            from = get_header(first_msg, "From").unwrap_or(from);
+            subject = get_header(first_msg, "Subject").unwrap_or(subject);
+            date = get_header(first_msg, "Date").unwrap_or(date);
+        }
+
+        let listing = format!(
+            "Thread ID: {}\nMessages: {}\nFrom: {}\nSubject: {}\nDate: {}\n\n",
+            thread_id,
+            messages.len(),
+            from,
+            subject,
+            date
+        );
+
+        // Lock so that listings from different threads don't interleave
+        let file_lock = file_lock.lock().unwrap();
+        match &output_file {
+            Some(output_file) => append_to_output(output_file, &listing),
+            None => print!("{}", listing),
+        }
+        drop(file_lock)
+    }
+    counter
+}
+
 /// Dequerer threads in the filter command utilize this method to grab the msg id
 /// from the ring buffer and get message content to write to output txt file
 pub async fn print_msgs(
@@ -1,82 +1,857 @@
 extern crate google_gmail1 as gmail1;
 pub mod cmd_args;
+pub mod daemon;
+pub mod dedupe;
+pub mod gmail_filters;
+pub mod journal;
+pub mod label_registry;
+pub mod labels;
 pub mod mail_service;
+pub mod protect;
+pub mod read_state;
 pub mod ringbuffer;
+pub mod rules;
+pub mod send_as;
+pub mod senders;
+pub mod storage;
+pub mod unsubscribe;
+pub mod vacation;
 
 use clap::Parser;
 use cmd_args::{self as cmd, Commands};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
 use mail_service::{self as mail, get_msg_ids_from_messages};
 use ringbuffer::MultiThreadedRingBuffer;
 use std::{
     collections::BTreeSet,
+    future::Future,
+    io::{self, Write},
     sync::{Arc, Mutex},
 };
 use tokio::sync::Mutex as tokio_mutex;
+use tokio::task::JoinHandle;
+
+type MsgIdBts = Arc<tokio_mutex<BTreeSet<Option<String>>>>;
+
+/// Spawns threads_num dequerer threads running the given dequeue method and threads_num
+/// enquerer threads moving msg ids from the BTreeSet into the ring buffer.
+/// Returns the (dequerer, enquerer) thread handles to be passed into join_msg_threads
+fn spawn_msg_threads<F, Fut>(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    threads_num: i64,
+    msg_id_bts: MsgIdBts,
+    msg_id_rb: &'static MultiThreadedRingBuffer<String>,
+    dequeue: F,
+) -> (Vec<JoinHandle<usize>>, Vec<JoinHandle<usize>>)
+where
+    F: Fn(Gmail<HttpsConnector<HttpConnector>>) -> Fut,
+    Fut: Future<Output = usize> + Send + 'static,
+{
+    // Thread reference: https://doc.rust-lang.org/std/thread/
+    let mut dequerer_threads: Vec<JoinHandle<usize>> =
+        Vec::with_capacity((threads_num).try_into().unwrap());
+    let mut enquerer_threads: Vec<JoinHandle<usize>> =
+        Vec::with_capacity((threads_num).try_into().unwrap());
+
+    for _ in 0..threads_num {
+        let msg_id_bts_clone = msg_id_bts.clone();
+        let dequeue_thread = tokio::spawn(dequeue(hub.clone()));
+        let enqueue_thread =
+            tokio::spawn(async move { mail::add_msgs(msg_id_bts_clone, msg_id_rb).await });
+        dequerer_threads.push(dequeue_thread);
+        enquerer_threads.push(enqueue_thread);
+    }
+
+    (dequerer_threads, enquerer_threads)
+}
+
+/// Tells the enquerer threads that no more msg ids are coming, poisons the ring buffer for the
+/// dequerer threads and waits for all of them to finish.
+/// Returns the (dequeued, enqueued) message counts reported by the threads
+async fn join_msg_threads(
+    threads: (Vec<JoinHandle<usize>>, Vec<JoinHandle<usize>>),
+    msg_id_bts: MsgIdBts,
+    msg_id_rb: &'static MultiThreadedRingBuffer<String>,
+) -> (usize, usize) {
+    let (mut dequerer_threads, mut enquerer_threads) = threads;
+
+    for _ in 0..enquerer_threads.len() {
+        let mut msg_id_bts_lock = msg_id_bts.lock().await;
+        msg_id_bts_lock.insert(None);
+    }
+
+    msg_id_rb.poison().await;
+
+    let mut messages_dequeued: usize = 0;
+    let mut messages_enqueued: usize = 0;
+    while let Some(curr_thread) = dequerer_threads.pop() {
+        messages_dequeued += curr_thread.await.unwrap();
+    }
+
+    while let Some(curr_thread) = enquerer_threads.pop() {
+        messages_enqueued += curr_thread.await.unwrap();
+    }
+
+    (messages_dequeued, messages_enqueued)
+}
+
+/// Spawns threads for a dry run, where the dequerer threads list the messages (or gmail threads
+/// when by_threads is set) a command would affect instead of acting on them. Protected threads
+/// are only left out of the listing when skip_protected is set, as for trash.
+/// Returns the (dequerer, enquerer) thread handles to be passed into join_msg_threads
+fn spawn_dry_run_threads(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    threads_num: i64,
+    msg_id_bts: MsgIdBts,
+    msg_id_rb: &'static MultiThreadedRingBuffer<String>,
+    output_file: Option<String>,
+    by_threads: bool,
+    skip_protected: bool,
+) -> (Vec<JoinHandle<usize>>, Vec<JoinHandle<usize>>) {
+    let file_lock = Arc::new(Mutex::new(0));
+    spawn_msg_threads(hub, threads_num, msg_id_bts, msg_id_rb, |hub| {
+        let output_file = output_file.clone();
+        let file_lock_clone = file_lock.clone();
+        async move {
+            if by_threads {
+                mail::preview_threads(
+                    &hub,
+                    msg_id_rb,
+                    output_file,
+                    file_lock_clone,
+                    skip_protected,
+                )
+                .await
+            } else {
+                mail::preview_msgs(&hub, msg_id_rb, output_file, file_lock_clone).await
+            }
+        }
+    })
+}
+
+/// Spawns threads for a command that changes messages (or gmail threads when by_threads is set).
+/// On a dry run the dequerer threads only list them, otherwise they run the given dequeue method.
+/// Returns the (dequerer, enquerer) thread handles to be passed into join_msg_threads
+fn spawn_action_threads<F, Fut>(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    threads_num: i64,
+    msg_id_bts: MsgIdBts,
+    msg_id_rb: &'static MultiThreadedRingBuffer<String>,
+    args: &cmd::Args,
+    by_threads: bool,
+    dequeue: F,
+) -> (Vec<JoinHandle<usize>>, Vec<JoinHandle<usize>>)
+where
+    F: Fn(Gmail<HttpsConnector<HttpConnector>>) -> Fut,
+    Fut: Future<Output = usize> + Send + 'static,
+{
+    if args.dry_run {
+        // Only trash skips protected threads, so only its preview leaves them out
+        let skip_protected = matches!(args.cmds, Commands::Trash(_));
+        spawn_dry_run_threads(
+            hub,
+            threads_num,
+            msg_id_bts,
+            msg_id_rb,
+            args.dry_run_output.clone(),
+            by_threads,
+            skip_protected,
+        )
+    } else {
+        spawn_msg_threads(hub, threads_num, msg_id_bts, msg_id_rb, dequeue)
+    }
+}
+
+/// Prints how many of the received messages (or threads, going by the noun) a command got
+/// through, and how many it couldn't
+fn report_msgs(noun: &str, verb: &str, past_verb: &str, done: usize, received: usize) {
+    if done < received {
+        println!("Could not {} {} {}", verb, received - done, noun);
+    }
+    println!("{} {} {}!", past_verb, done, noun);
+}
+
+/// Turns on protection for trash and delete, printing the error if the protection config
+/// couldn't be loaded. Returns whether protection was loaded
+async fn load_protection(hub: &Gmail<HttpsConnector<HttpConnector>>) -> bool {
+    match protect::load(hub).await {
+        Ok(()) => true,
+        Err(e) => {
+            println!("{}", e);
+            false
+        }
+    }
+}
+
+/// The noun used when reporting on messages or, in threads mode, on gmail threads
+fn msg_noun(by_threads: bool) -> &'static str {
+    if by_threads {
+        "threads"
+    } else {
+        "messages"
+    }
+}
+
+/// The label ids a command adds and removes on messages, plus the verb and past verb
+/// describing the change for reporting (e.g. "star" and "Starred").
+/// Only messages carrying every label id in required_label_ids are picked up by labels or filter.
+/// by_threads changes every message in the threads of the selected messages instead
+struct ModifyAction {
+    add_label_ids: Vec<String>,
+    remove_label_ids: Vec<String>,
+    required_label_ids: &'static [&'static str],
+    verb: &'static str,
+    past_verb: &'static str,
+    by_threads: bool,
+}
+
+impl ModifyAction {
+    /// The change made by a label shortcut like archive or star, on the messages (or threads
+    /// with --by-thread) its action options pick out
+    fn shortcut(
+        add_label_ids: &[&str],
+        remove_label_ids: &[&str],
+        verb: &'static str,
+        past_verb: &'static str,
+        action: &cmd::MsgAction,
+    ) -> Self {
+        ModifyAction {
+            add_label_ids: add_label_ids.iter().map(|id| id.to_string()).collect(),
+            remove_label_ids: remove_label_ids.iter().map(|id| id.to_string()).collect(),
+            required_label_ids: &[],
+            verb,
+            past_verb,
+            by_threads: action.by_thread,
+        }
+    }
+}
+
+/// Runs a label shortcut like archive or star on the messages its action options pick out
+async fn run_shortcut_cmd(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    action: &cmd::MsgAction,
+    modify: ModifyAction,
+    msg_id_bts: MsgIdBts,
+    msg_id_rb: &'static MultiThreadedRingBuffer<String>,
+    args: &cmd::Args,
+) {
+    run_modify_cmd(
+        hub,
+        action.threads_num,
+        action.action_opt.clone(),
+        modify,
+        msg_id_bts,
+        msg_id_rb,
+        args,
+    )
+    .await;
+}
+
+/// Runs a command that adds and removes label ids on the messages picked by trash_opt through
+/// the multithreaded ring buffer, then reports how many messages were changed.
+/// Only messages whose labels actually change are modified and journaled, so undo puts back
+/// exactly what was there. Threads are all modified, but only journaled for the label ids that
+/// none of their messages carried before (added) or that some did (removed)
+async fn run_modify_cmd(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    threads_num: i64,
+    trash_opt: cmd::TrashOptions,
+    action: ModifyAction,
+    msg_id_bts: MsgIdBts,
+    msg_id_rb: &'static MultiThreadedRingBuffer<String>,
+    args: &cmd::Args,
+) {
+    let ModifyAction {
+        add_label_ids,
+        remove_label_ids,
+        required_label_ids,
+        verb,
+        past_verb,
+        by_threads,
+    } = action;
+
+    // Everything is collected up front so what each message carries can be checked before
+    // anything changes
+    let selected_bts: MsgIdBts = Arc::new(tokio_mutex::new(BTreeSet::new()));
+    if by_threads {
+        mail::add_thread_ids_from_opt(
+            hub,
+            trash_opt.clone(),
+            required_label_ids,
+            selected_bts.clone(),
+        )
+        .await;
+    } else {
+        mail::add_msg_ids_from_opt(
+            hub,
+            trash_opt.clone(),
+            required_label_ids,
+            selected_bts.clone(),
+        )
+        .await;
+    }
+    let selected: BTreeSet<String> = selected_bts
+        .lock()
+        .await
+        .iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let changes = Arc::new(
+        mail::label_changes(
+            hub,
+            &trash_opt,
+            required_label_ids,
+            &selected,
+            &add_label_ids,
+            &remove_label_ids,
+            by_threads,
+        )
+        .await,
+    );
+    let ids = if by_threads {
+        selected
+    } else {
+        changes.changed_ids()
+    };
+
+    let threads = spawn_action_threads(
+        hub,
+        threads_num,
+        msg_id_bts.clone(),
+        msg_id_rb,
+        args,
+        by_threads,
+        |hub| {
+            let changes = changes.clone();
+            async move {
+                if by_threads {
+                    mail::modify_threads(&hub, msg_id_rb, changes).await
+                } else {
+                    mail::modify_msgs(&hub, msg_id_rb, changes).await
+                }
+            }
+        },
+    );
+
+    {
+        let mut msg_id_bts_lock = msg_id_bts.lock().await;
+        msg_id_bts_lock.extend(ids.into_iter().map(Some));
+    }
+
+    let (messages_modified, messages_received) =
+        join_msg_threads(threads, msg_id_bts, msg_id_rb).await;
+
+    let noun = msg_noun(by_threads);
+    if args.dry_run {
+        report_dry_run(
+            noun,
+            &past_verb.to_lowercase(),
+            messages_received,
+            0,
+            &args.dry_run_output,
+        );
+        return;
+    }
+
+    report_msgs(noun, verb, past_verb, messages_modified, messages_received);
+}
+
+/// Prints the total number of messages (or threads, going by the noun) a dry run found, and how
+/// many protected ones it left out, also adding it to the end of the dry run output file if there is one
+fn report_dry_run(
+    noun: &str,
+    action: &str,
+    found: usize,
+    protected: usize,
+    output_file: &Option<String>,
+) {
+    let mut total = format!("{} {} would be {}", found, noun, action);
+    if protected > 0 {
+        total.push_str(&format!(", skipping {} protected {}", protected, noun));
+    }
+    if let Some(output_file) = output_file {
+        mail::append_to_output(output_file, &format!("Total: {}\n", total));
+    }
+    println!("Dry run: {}", total);
+}
+
+/// Asks the user a yes/no question on stdin; anything other than y or yes is a no
+pub fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N]: ", prompt);
+    io::stdout().flush().expect("Could not flush stdout");
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
 
 #[tokio::main]
 async fn main() {
     static MSG_ID_RB: MultiThreadedRingBuffer<String, 1024> = MultiThreadedRingBuffer::new();
-    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
-        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    let msg_id_bts: MsgIdBts = Arc::new(tokio_mutex::new(BTreeSet::new()));
     let hub = mail::create_client().await.unwrap();
     let args = cmd::Args::parse();
+    label_registry::set_cache_ttl(args.label_cache_ttl);
 
     // println!("Args: {args:?}");
 
     match args.cmds {
-        Commands::Trash(trash) => {
-            // Thread reference: https://doc.rust-lang.org/std/thread/
-            let mut dequerer_threads: Vec<tokio::task::JoinHandle<usize>> =
-                Vec::with_capacity((trash.threads_num).try_into().unwrap());
-            let mut enquerer_threads: Vec<tokio::task::JoinHandle<usize>> =
-                Vec::with_capacity((trash.threads_num).try_into().unwrap());
-
-            for _ in 0..trash.threads_num {
-                let hub_clone = hub.clone();
-                let msg_id_bts_clone = msg_id_bts.clone();
-                let dequeue_thread =
-                    tokio::spawn(async move { mail::trash_msgs(&hub_clone, &MSG_ID_RB).await });
-                let enqueue_thread =
-                    tokio::spawn(async move { mail::add_msgs(msg_id_bts_clone, &MSG_ID_RB).await });
-                dequerer_threads.push(dequeue_thread);
-                enquerer_threads.push(enqueue_thread);
+        Commands::Trash(ref trash) => {
+            if !trash.include_protected && !load_protection(&hub).await {
+                return;
             }
 
-            match trash.trash_opt {
-                cmd_args::TrashOptions::ByMsgIds(msg_ids) => {
-                    mail::add_msg_ids_from_ids(&hub, msg_ids.msg_ids, msg_id_bts.clone()).await;
+            // Messages trashed by read time have to be picked out before any threads are spawned
+            let read_msg_ids = match trash.read_more_than {
+                Some(ref read_more_than) => {
+                    let result = read_state::msg_ids_read_more_than(
+                        &hub,
+                        trash.trash_opt.clone(),
+                        read_more_than,
+                    )
+                    .await;
+                    match result {
+                        Ok(msg_ids) => Some(msg_ids),
+                        Err(e) => {
+                            println!("{}", e);
+                            return;
+                        }
+                    }
                 }
-                cmd_args::TrashOptions::ByLabels(labels) => {
-                    mail::add_msg_ids_from_labels(&hub, labels.labels, msg_id_bts.clone()).await;
+                None => None,
+            };
+
+            let by_threads = trash.by_thread;
+            let threads = spawn_action_threads(
+                &hub,
+                trash.threads_num,
+                msg_id_bts.clone(),
+                &MSG_ID_RB,
+                &args,
+                by_threads,
+                |hub| async move {
+                    if by_threads {
+                        mail::trash_threads(&hub, &MSG_ID_RB).await
+                    } else {
+                        mail::trash_msgs(&hub, &MSG_ID_RB).await
+                    }
+                },
+            );
+
+            // Protected messages are left out while the msg ids are collected
+            if let Some(read_msg_ids) = read_msg_ids {
+                let mut msg_id_bts_lock = msg_id_bts.lock().await;
+                msg_id_bts_lock.extend(read_msg_ids.into_iter().map(Some));
+            } else if by_threads {
+                mail::add_thread_ids_from_opt(
+                    &hub,
+                    trash.trash_opt.clone(),
+                    &[],
+                    msg_id_bts.clone(),
+                )
+                .await;
+            } else {
+                protect::add_unprotected_msg_ids_from_opt(
+                    &hub,
+                    trash.trash_opt.clone(),
+                    msg_id_bts.clone(),
+                )
+                .await;
+            }
+
+            let (messages_trashed, messages_received) =
+                join_msg_threads(threads, msg_id_bts.clone(), &MSG_ID_RB).await;
+
+            let noun = msg_noun(by_threads);
+            let skipped = protect::take_skipped();
+            // Protected threads are only found once the dequerer threads look into them, so
+            // they were received before they were skipped
+            let messages_received = if by_threads {
+                messages_received.saturating_sub(skipped.total())
+            } else {
+                messages_received
+            };
+            if args.dry_run {
+                report_dry_run(
+                    noun,
+                    "trashed",
+                    messages_received,
+                    skipped.total(),
+                    &args.dry_run_output,
+                );
+                skipped.print(noun);
+                return;
+            }
+
+            report_msgs(
+                noun,
+                "trash",
+                "Trashed",
+                messages_trashed,
+                messages_received,
+            );
+            skipped.print(noun);
+        }
+        Commands::Untrash(ref untrash) => {
+            let threads = spawn_action_threads(
+                &hub,
+                untrash.threads_num,
+                msg_id_bts.clone(),
+                &MSG_ID_RB,
+                &args,
+                false,
+                |hub| async move { mail::untrash_msgs(&hub, &MSG_ID_RB).await },
+            );
+
+            // Only messages sitting in the trash need to be restored
+            mail::add_msg_ids_from_opt(
+                &hub,
+                untrash.untrash_opt.clone(),
+                &["TRASH"],
+                msg_id_bts.clone(),
+            )
+            .await;
+
+            let (messages_untrashed, messages_received) =
+                join_msg_threads(threads, msg_id_bts.clone(), &MSG_ID_RB).await;
+
+            if args.dry_run {
+                report_dry_run(
+                    "messages",
+                    "untrashed",
+                    messages_received,
+                    0,
+                    &args.dry_run_output,
+                );
+                return;
+            }
+
+            report_msgs(
+                "messages",
+                "untrash",
+                "Untrashed",
+                messages_untrashed,
+                messages_received,
+            );
+        }
+        Commands::Label(ref label) => {
+            let add_label_ids = match mail::get_label_ids(&hub, &label.add).await {
+                Ok(label_ids) => label_ids,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
                 }
-                cmd_args::TrashOptions::ByFilter(filter) => {
-                    mail::get_msg_ids_from_messages(&hub, None, Some(*filter), msg_id_bts.clone())
-                        .await;
+            };
+            let remove_label_ids = match mail::get_label_ids(&hub, &label.remove).await {
+                Ok(label_ids) => label_ids,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
                 }
+            };
+
+            run_modify_cmd(
+                &hub,
+                label.threads_num,
+                label.label_opt.clone(),
+                ModifyAction {
+                    add_label_ids,
+                    remove_label_ids,
+                    required_label_ids: &[],
+                    verb: "relabel",
+                    past_verb: "Relabeled",
+                    by_threads: label.by_thread,
+                },
+                msg_id_bts.clone(),
+                &MSG_ID_RB,
+                &args,
+            )
+            .await;
+        }
+        Commands::Archive(ref action) => {
+            let modify = ModifyAction::shortcut(&[], &["INBOX"], "archive", "Archived", action);
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::MarkRead(ref action) => {
+            let modify =
+                ModifyAction::shortcut(&[], &["UNREAD"], "mark as read", "Marked as read", action);
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::MarkUnread(ref action) => {
+            let modify = ModifyAction::shortcut(
+                &["UNREAD"],
+                &[],
+                "mark as unread",
+                "Marked as unread",
+                action,
+            );
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::Star(ref action) => {
+            let modify = ModifyAction::shortcut(&["STARRED"], &[], "star", "Starred", action);
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::Unstar(ref action) => {
+            let modify = ModifyAction::shortcut(&[], &["STARRED"], "unstar", "Unstarred", action);
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::Spam(ref action) => {
+            let modify = ModifyAction::shortcut(
+                &["SPAM"],
+                &["INBOX"],
+                "report as spam",
+                "Reported as spam",
+                action,
+            );
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::NotSpam(ref action) => {
+            // Only messages sitting in spam need to be moved out of it
+            let modify = ModifyAction {
+                required_label_ids: &["SPAM"],
+                ..ModifyAction::shortcut(
+                    &["INBOX"],
+                    &["SPAM"],
+                    "move out of spam",
+                    "Moved out of spam",
+                    action,
+                )
+            };
+            run_shortcut_cmd(&hub, action, modify, msg_id_bts.clone(), &MSG_ID_RB, &args).await;
+        }
+        Commands::Delete(ref delete) => {
+            if !delete.include_protected && !load_protection(&hub).await {
+                return;
             }
 
-            for _ in 0..trash.threads_num {
-                let mut msg_id_bts_lock = msg_id_bts.lock().await;
-                msg_id_bts_lock.insert(None);
+            // Every message has to be collected up front so the user knows how many are
+            // about to be deleted before anything happens. Protected messages are left out
+            // before the count is shown or confirmed
+            protect::add_unprotected_msg_ids_from_opt(
+                &hub,
+                delete.delete_opt.clone(),
+                msg_id_bts.clone(),
+            )
+            .await;
+            let msg_ids: Vec<String> = msg_id_bts.lock().await.iter().flatten().cloned().collect();
+            let skipped = protect::take_skipped();
+            skipped.print("messages");
+
+            if msg_ids.is_empty() {
+                println!("No messages matched, nothing to delete");
+                return;
+            }
+
+            if args.dry_run {
+                let threads = spawn_dry_run_threads(
+                    &hub,
+                    1,
+                    msg_id_bts.clone(),
+                    &MSG_ID_RB,
+                    args.dry_run_output.clone(),
+                    false,
+                    true,
+                );
+                let (_messages_listed, messages_found) =
+                    join_msg_threads(threads, msg_id_bts.clone(), &MSG_ID_RB).await;
+                report_dry_run(
+                    "messages",
+                    "permanently deleted",
+                    messages_found,
+                    skipped.total(),
+                    &args.dry_run_output,
+                );
+                return;
+            }
+
+            if msg_ids.len() > delete.max_count && !delete.force {
+                println!(
+                    "Refusing to permanently delete {} messages, which is more than the max count of {}.\nRaise --max-count or pass --force to delete them anyway",
+                    msg_ids.len(),
+                    delete.max_count
+                );
+                return;
+            }
+
+            if !delete.yes
+                && !confirm(&format!(
+                    "Permanently delete {} messages? This cannot be undone",
+                    msg_ids.len()
+                ))
+            {
+                println!("Delete cancelled");
+                return;
             }
 
-            MSG_ID_RB.poison().await;
+            let messages_deleted = mail::delete_msgs(&hub, &msg_ids).await;
+            if messages_deleted < msg_ids.len() {
+                println!(
+                    "Could not delete {} messages",
+                    msg_ids.len() - messages_deleted
+                );
+            }
+            println!("Deleted {} messages!", messages_deleted);
+        }
+        Commands::Unsubscribe(ref unsubscribe) => {
+            unsubscribe::unsubscribe(&hub, unsubscribe, args.dry_run).await;
+        }
+        Commands::Dedupe(ref dedupe) => {
+            let mut groups =
+                dedupe::find_duplicate_groups(&hub, dedupe.dedupe_opt.clone(), dedupe.content_hash)
+                    .await;
+            let duplicates: Vec<String> = groups
+                .iter()
+                .flat_map(|group| group.duplicates.iter().cloned())
+                .collect();
 
-            let mut messages_trashed: usize = 0;
-            let mut messages_received: usize = 0;
-            while let Some(curr_thread) = dequerer_threads.pop() {
-                messages_trashed += curr_thread.await.unwrap();
+            // Protected duplicates are left out before anything is listed or trashed
+            if !dedupe.include_protected && !load_protection(&hub).await {
+                return;
+            }
+            let unprotected: BTreeSet<String> =
+                protect::unprotected_msg_ids_from_opt(&hub, &dedupe.dedupe_opt, &duplicates)
+                    .await
+                    .into_iter()
+                    .collect();
+            let skipped = protect::take_skipped();
+            for group in &mut groups {
+                group
+                    .duplicates
+                    .retain(|msg_id| unprotected.contains(msg_id));
             }
+            groups.retain(|group| !group.duplicates.is_empty());
+            let duplicates: Vec<String> = unprotected.into_iter().collect();
 
-            while let Some(curr_thread) = enquerer_threads.pop() {
-                messages_received += curr_thread.await.unwrap();
+            if args.dry_run {
+                dedupe::preview_groups(&hub, &groups, &args.dry_run_output).await;
+                report_dry_run(
+                    "duplicate messages",
+                    "trashed",
+                    duplicates.len(),
+                    skipped.total(),
+                    &args.dry_run_output,
+                );
+                skipped.print("duplicate messages");
+                return;
             }
 
-            assert_eq!(messages_trashed, messages_received);
-            println!("Trashed {} messages!", messages_trashed);
+            skipped.print("duplicate messages");
+            if duplicates.is_empty() {
+                println!("No duplicate messages found");
+                return;
+            }
+
+            println!(
+                "Found {} duplicate messages in {} groups",
+                duplicates.len(),
+                groups.len()
+            );
+            let threads = spawn_msg_threads(
+                &hub,
+                dedupe.threads_num,
+                msg_id_bts.clone(),
+                &MSG_ID_RB,
+                |hub| async move { mail::trash_msgs(&hub, &MSG_ID_RB).await },
+            );
+            msg_id_bts
+                .lock()
+                .await
+                .extend(duplicates.into_iter().map(Some));
+
+            let (messages_trashed, messages_received) =
+                join_msg_threads(threads, msg_id_bts.clone(), &MSG_ID_RB).await;
+
+            report_msgs(
+                "duplicate messages",
+                "trash",
+                "Trashed",
+                messages_trashed,
+                messages_received,
+            );
+        }
+        Commands::Senders(ref senders) => {
+            senders::senders_report(&hub, senders, args.dry_run).await;
+        }
+        Commands::Storage(ref storage) => {
+            storage::storage_report(&hub, storage, args.dry_run).await;
         }
-        Commands::Send(send) => {
-            let result = mail::send_message(*send.clone(), send.json_file).await;
+        Commands::ApplyRules(ref apply_rules) => {
+            if !load_protection(&hub).await {
+                return;
+            }
+
+            let rules = match rules::load_rules(&apply_rules.file) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+
+            let summaries = rules::apply_rules(
+                &hub,
+                &rules,
+                apply_rules.yes,
+                args.dry_run,
+                &args.dry_run_output,
+            )
+            .await;
+
+            println!("\nRules summary:");
+            for summary in summaries {
+                println!("{}", summary.describe());
+            }
+        }
+        Commands::Daemon(ref daemon) => {
+            if !load_protection(&hub).await {
+                return;
+            }
+
+            let result = daemon::run_daemon(
+                &hub,
+                &daemon.file,
+                &daemon.log,
+                daemon.yes,
+                args.dry_run,
+                &args.dry_run_output,
+            )
+            .await;
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+        }
+        Commands::ReadState(ref read_state_opt) => {
+            let result = match read_state_opt {
+                cmd::ReadStateOptions::Watch(labels) => {
+                    read_state::watch_labels(&hub, &labels.labels, true).await
+                }
+                cmd::ReadStateOptions::Unwatch(labels) => {
+                    read_state::watch_labels(&hub, &labels.labels, false).await
+                }
+                cmd::ReadStateOptions::Sync => read_state::sync(&hub).await.map(|_state| ()),
+            };
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+        }
+        Commands::Undo(ref undo) => {
+            let result = if undo.list {
+                journal::list_runs()
+            } else {
+                journal::undo_run(&hub, &undo.run_id, args.dry_run).await
+            };
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+        }
+        Commands::Send(ref send) => {
+            let result = mail::send_message(*send.clone(), send.json_file.clone()).await;
             match result {
                 Err(e) => {
                     println!("{:?}", e)
@@ -84,61 +859,101 @@ async fn main() {
                 Ok(_res) => {}
             };
         }
-        Commands::Labels => {
-            let labels_btreemap = mail::list_labels(&hub).await;
-            if let Ok(labels_btreemap) = labels_btreemap {
-                let size = labels_btreemap.len();
-                let mut count = 0;
-                print!("All Labels in authenticated user's inbox: ");
-                for label_id_pair in labels_btreemap {
-                    count += 1;
-                    if count != size {
-                        print!("{}, ", label_id_pair.0);
-                    } else {
-                        print!("{}", label_id_pair.0);
-                    }
+        Commands::Labels(ref labels_cmd) => {
+            let result = match labels_cmd.labels_opt {
+                Some(cmd::LabelsOptions::Create(ref create)) => {
+                    labels::create_label(&hub, &create.name, args.dry_run).await
+                }
+                Some(cmd::LabelsOptions::Rename(ref rename)) => {
+                    labels::rename_label(&hub, &rename.from, &rename.to, args.dry_run).await
+                }
+                Some(cmd::LabelsOptions::Delete(ref delete)) => {
+                    labels::delete_label(&hub, &delete.name, delete.recursive, args.dry_run).await
+                }
+                Some(cmd::LabelsOptions::Tree) => labels::print_label_tree(&hub).await,
+                Some(cmd::LabelsOptions::Export(ref export)) => {
+                    labels::export_labels(&hub, &export.file).await
                 }
+                Some(cmd::LabelsOptions::Import(ref import)) => {
+                    labels::import_labels(&hub, &import.file, args.dry_run).await
+                }
+                None => labels::print_label_details(&hub, labels_cmd.format).await,
+            };
+            if let Err(e) = result {
+                println!("{}", e);
             }
         }
-        Commands::Filter(filter) => {
-            let file_lock = Arc::new(Mutex::new(0));
-            let mut dequerer_threads: Vec<tokio::task::JoinHandle<usize>> =
-                Vec::with_capacity((filter.threads).try_into().unwrap());
-            let mut enquerer_threads: Vec<tokio::task::JoinHandle<usize>> =
-                Vec::with_capacity((filter.threads).try_into().unwrap());
-
-            for _ in 0..filter.threads {
-                let hub_clone = hub.clone();
-                let msg_id_bts_clone = msg_id_bts.clone();
-                let output_file = filter.output.clone();
-                let file_lock_clone = file_lock.clone();
-                let dequeue_thread = tokio::spawn(async move {
-                    mail::print_msgs(&hub_clone, &MSG_ID_RB, output_file, file_lock_clone).await
-                });
-                let enqueue_thread =
-                    tokio::spawn(async move { mail::add_msgs(msg_id_bts_clone, &MSG_ID_RB).await });
-                dequerer_threads.push(dequeue_thread);
-                enquerer_threads.push(enqueue_thread);
-            }
-
-            get_msg_ids_from_messages(&hub, None, Some(filter.filter), msg_id_bts.clone()).await;
-
-            for _ in 0..filter.threads {
-                let mut msg_id_bts_lock = msg_id_bts.lock().await;
-                msg_id_bts_lock.insert(None);
+        Commands::GmailFilters(ref gmail_filters_opt) => {
+            let result = match gmail_filters_opt {
+                cmd::GmailFiltersOptions::List => gmail_filters::print_filters(&hub).await,
+                cmd::GmailFiltersOptions::Create(create) => {
+                    gmail_filters::create_filter(&hub, create, args.dry_run).await
+                }
+                cmd::GmailFiltersOptions::Delete(delete) => {
+                    gmail_filters::delete_filters(&hub, &delete.ids, args.dry_run).await
+                }
+                cmd::GmailFiltersOptions::Export(export) => {
+                    gmail_filters::export_filters(&hub, &export.file).await
+                }
+                cmd::GmailFiltersOptions::Import(import) => {
+                    gmail_filters::import_filters(&hub, &import.file, args.dry_run).await
+                }
+            };
+            if let Err(e) = result {
+                println!("{}", e);
             }
-
-            MSG_ID_RB.poison().await;
-
-            let mut messages_found: usize = 0;
-            let mut messages_printed: usize = 0;
-            while let Some(curr_thread) = dequerer_threads.pop() {
-                messages_printed += curr_thread.await.unwrap();
+        }
+        Commands::Vacation(ref vacation_opt) => {
+            let result = match vacation_opt {
+                cmd::VacationOptions::Show => vacation::show_vacation(&hub).await,
+                cmd::VacationOptions::Enable(enable) => {
+                    vacation::enable_vacation(&hub, enable, args.dry_run).await
+                }
+                cmd::VacationOptions::Disable => {
+                    vacation::disable_vacation(&hub, args.dry_run).await
+                }
+            };
+            if let Err(e) = result {
+                println!("{}", e);
             }
-
-            while let Some(curr_thread) = enquerer_threads.pop() {
-                messages_found += curr_thread.await.unwrap();
+        }
+        Commands::SendAs(ref send_as_opt) => {
+            let result = match send_as_opt {
+                cmd::SendAsOptions::List => send_as::print_aliases(&hub).await,
+                cmd::SendAsOptions::Signature(signature) => {
+                    send_as::set_signature(&hub, signature, args.dry_run).await
+                }
+                cmd::SendAsOptions::Default(default) => {
+                    send_as::set_default(&hub, default, args.dry_run).await
+                }
+                cmd::SendAsOptions::ReplyTo(reply_to) => {
+                    send_as::set_reply_to(&hub, reply_to, args.dry_run).await
+                }
+            };
+            if let Err(e) = result {
+                println!("{}", e);
             }
+        }
+        Commands::Filter(ref filter) => {
+            let file_lock = Arc::new(Mutex::new(0));
+            let output_file = filter.output.clone();
+            let threads = spawn_msg_threads(
+                &hub,
+                filter.threads,
+                msg_id_bts.clone(),
+                &MSG_ID_RB,
+                |hub| {
+                    let output_file = output_file.clone();
+                    let file_lock_clone = file_lock.clone();
+                    async move { mail::print_msgs(&hub, &MSG_ID_RB, output_file, file_lock_clone).await }
+                },
+            );
+
+            get_msg_ids_from_messages(&hub, &[], Some(filter.filter.clone()), msg_id_bts.clone())
+                .await;
+
+            let (messages_printed, messages_found) =
+                join_msg_threads(threads, msg_id_bts.clone(), &MSG_ID_RB).await;
 
             assert_eq!(messages_found, messages_printed);
             println!("Found {} messages!", messages_found);
@@ -0,0 +1,654 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::api::Message;
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::{Filter, TrashOptions};
+use crate::mail_service as mail;
+use crate::rules;
+
+/// Protection config read from the directory the program is run from, if it exists
+pub const PROTECT_FILE: &str = "protect.toml";
+
+/// Protection that trash and delete consult, set up by load() before they run
+static PROTECTION: Mutex<Option<Protection>> = Mutex::new(None);
+
+/// How many messages (or threads) protection has skipped since the last take_skipped() call
+static SKIPPED: Mutex<SkipReport> = Mutex::new(SkipReport::new());
+
+/// Which messages are kept out of trash and delete
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProtectConfig {
+    /// Keep starred messages (on by default)
+    #[serde(default = "default_true")]
+    pub starred: bool,
+
+    /// Keep messages gmail marked as important (on by default)
+    #[serde(default = "default_true")]
+    pub important: bool,
+
+    /// Keep messages from these sender addresses
+    #[serde(default)]
+    pub senders: Vec<String>,
+
+    /// Keep messages from these domains and their subdomains
+    #[serde(default)]
+    pub domains: Vec<String>,
+
+    /// Keep messages carrying any of these label names
+    #[serde(default)]
+    pub keep_labels: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for ProtectConfig {
+    fn default() -> Self {
+        ProtectConfig {
+            starred: true,
+            important: true,
+            senders: Vec::new(),
+            domains: Vec::new(),
+            keep_labels: Vec::new(),
+        }
+    }
+}
+
+/// A protection config with its senders and domains lowercased and its keep labels resolved to ids
+#[derive(Debug, Clone)]
+struct Protection {
+    starred: bool,
+    important: bool,
+    senders: Vec<String>,
+    domains: Vec<String>,
+    keep_label_ids: Vec<String>,
+}
+
+/// How the protected messages of a reason are picked out: by Gmail search terms, any of which a
+/// message can match, or by label ids, any of which a message can carry
+#[derive(Debug, PartialEq)]
+enum Matcher {
+    Terms(Vec<String>),
+    LabelIds(Vec<String>),
+}
+
+/// Why a message was protected
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SkipReason {
+    Starred,
+    Important,
+    KeepLabel,
+    Sender,
+    Domain,
+    Unchecked,
+}
+
+/// Counts of the messages (or threads) protection skipped, by reason
+#[derive(Debug, Clone, Default)]
+pub struct SkipReport {
+    pub starred: usize,
+    pub important: usize,
+    pub keep_label: usize,
+    pub sender: usize,
+    pub domain: usize,
+    pub unchecked: usize,
+
+    /// Messages already counted, so one picked out by several selectors is only counted once
+    counted_msg_ids: BTreeSet<String>,
+}
+
+impl SkipReport {
+    const fn new() -> Self {
+        SkipReport {
+            starred: 0,
+            important: 0,
+            keep_label: 0,
+            sender: 0,
+            domain: 0,
+            unchecked: 0,
+            counted_msg_ids: BTreeSet::new(),
+        }
+    }
+
+    fn add_msg(&mut self, msg_id: &str, reason: SkipReason) {
+        if self.counted_msg_ids.insert(msg_id.to_string()) {
+            self.add(reason);
+        }
+    }
+
+    fn add(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::Starred => self.starred += 1,
+            SkipReason::Important => self.important += 1,
+            SkipReason::KeepLabel => self.keep_label += 1,
+            SkipReason::Sender => self.sender += 1,
+            SkipReason::Domain => self.domain += 1,
+            SkipReason::Unchecked => self.unchecked += 1,
+        }
+    }
+
+    /// Total number of skipped messages (or threads)
+    pub fn total(&self) -> usize {
+        self.starred + self.important + self.keep_label + self.sender + self.domain + self.unchecked
+    }
+
+    /// One line description of why messages were skipped, e.g. "3 starred, 1 important"
+    pub fn describe(&self) -> String {
+        let reasons = [
+            (self.starred, "starred"),
+            (self.important, "important"),
+            (self.keep_label, "with a keep label"),
+            (self.sender, "from an allowlisted sender"),
+            (self.domain, "from an allowlisted domain"),
+            (self.unchecked, "that couldn't be checked"),
+        ];
+        reasons
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, reason)| format!("{} {}", count, reason))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Prints how many were skipped and why, if any were
+    pub fn print(&self, noun: &str) {
+        if self.total() > 0 {
+            println!(
+                "Skipped {} protected {} ({})",
+                self.total(),
+                noun,
+                self.describe()
+            );
+        }
+    }
+}
+
+/// Reads the protection config (falling back to protecting starred and important messages
+/// when there's no config file) and turns protection on for trash and delete
+pub async fn load(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config: ProtectConfig = if Path::new(PROTECT_FILE).exists() {
+        rules::read_toml_or_json(PROTECT_FILE)?
+    } else {
+        ProtectConfig::default()
+    };
+
+    let keep_label_ids = mail::get_label_ids(hub, &config.keep_labels)
+        .await
+        .map_err(|e| format!("Keep label in {}: {}", PROTECT_FILE, e))?;
+
+    let protection = Protection {
+        starred: config.starred,
+        important: config.important,
+        senders: config
+            .senders
+            .iter()
+            .map(|sender| sender.to_lowercase())
+            .collect(),
+        domains: config
+            .domains
+            .iter()
+            .map(|domain| domain.trim_start_matches('@').to_lowercase())
+            .collect(),
+        keep_label_ids,
+    };
+    *PROTECTION.lock().unwrap() = Some(protection);
+    Ok(())
+}
+
+/// Returns the skipped counts since the last call and resets them
+pub fn take_skipped() -> SkipReport {
+    std::mem::take(&mut *SKIPPED.lock().unwrap())
+}
+
+impl Protection {
+    /// What picks out protected messages for each reason, in the order reasons are checked.
+    /// Keep labels go by their ids, since how Gmail search writes a label name with punctuation
+    /// or non-ASCII letters in it can't be relied on
+    fn matchers(&self) -> Vec<(SkipReason, Matcher)> {
+        let mut matchers = Vec::new();
+        if self.starred {
+            matchers.push((
+                SkipReason::Starred,
+                Matcher::Terms(vec!["is:starred".to_string()]),
+            ));
+        }
+        if self.important {
+            matchers.push((
+                SkipReason::Important,
+                Matcher::Terms(vec!["is:important".to_string()]),
+            ));
+        }
+        if !self.keep_label_ids.is_empty() {
+            matchers.push((
+                SkipReason::KeepLabel,
+                Matcher::LabelIds(self.keep_label_ids.clone()),
+            ));
+        }
+        if !self.senders.is_empty() {
+            matchers.push((
+                SkipReason::Sender,
+                Matcher::Terms(
+                    self.senders
+                        .iter()
+                        .map(|sender| format!("from:{}", sender))
+                        .collect(),
+                ),
+            ));
+        }
+        // Without an @ the domain also matches its subdomains, e.g. from:example.com matching
+        // news@mail.example.com
+        if !self.domains.is_empty() {
+            matchers.push((
+                SkipReason::Domain,
+                Matcher::Terms(
+                    self.domains
+                        .iter()
+                        .map(|domain| format!("from:{}", domain))
+                        .collect(),
+                ),
+            ));
+        }
+        matchers
+    }
+
+    /// Search terms leaving out the protected messages that can be searched for, e.g.
+    /// "-is:starred -is:important". Messages with a keep label have to be taken out by id
+    fn exclusion_query(&self) -> String {
+        self.matchers()
+            .iter()
+            .flat_map(|(_reason, matcher)| match matcher {
+                Matcher::Terms(terms) => terms.as_slice(),
+                Matcher::LabelIds(_label_ids) => &[],
+            })
+            .map(|term| format!("-{}", term))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// Checks the message's labels and sender, returning why it's protected if it is
+    fn check(&self, msg: &Message) -> Option<SkipReason> {
+        let label_ids = msg.label_ids.as_deref().unwrap_or_default();
+        let has_label = |label_id: &str| label_ids.iter().any(|id| id == label_id);
+
+        if self.starred && has_label("STARRED") {
+            return Some(SkipReason::Starred);
+        }
+        if self.important && has_label("IMPORTANT") {
+            return Some(SkipReason::Important);
+        }
+        if self.keep_label_ids.iter().any(|id| has_label(id)) {
+            return Some(SkipReason::KeepLabel);
+        }
+
+        let sender = mail::sender_address(&mail::get_header(msg, "From").unwrap_or_default());
+        if self.senders.contains(&sender) {
+            return Some(SkipReason::Sender);
+        }
+        let domain = sender.rsplit_once('@').map(|(_, domain)| domain);
+        if let Some(domain) = domain {
+            let allowlisted = self
+                .domains
+                .iter()
+                .any(|allowed| domain == allowed || domain.ends_with(&format!(".{}", allowed)));
+            if allowlisted {
+                return Some(SkipReason::Domain);
+            }
+        }
+        None
+    }
+}
+
+/// The protection trash and delete consult right now, if it was loaded
+fn current() -> Option<Protection> {
+    PROTECTION.lock().unwrap().clone()
+}
+
+/// Lists the protected messages among the ones carrying every label id in label_ids and matching
+/// the filter, along with why each is protected.
+/// This takes one messages.list pass per reason (and per keep label) rather than a fetch per message
+async fn list_protected(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    protection: &Protection,
+    label_ids: &[&str],
+    filter: Option<Filter>,
+) -> Result<BTreeMap<String, SkipReason>, Box<dyn std::error::Error>> {
+    let mut protected: BTreeMap<String, SkipReason> = BTreeMap::new();
+    for (reason, matcher) in protection.matchers() {
+        let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+            Arc::new(tokio_mutex::new(BTreeSet::new()));
+        match matcher {
+            Matcher::Terms(terms) => {
+                // Terms within braces are OR'ed together
+                let query = format!("{{{}}}", terms.join(" "));
+                mail::get_msg_ids_from_query(
+                    hub,
+                    label_ids,
+                    filter.clone(),
+                    &query,
+                    msg_id_bts.clone(),
+                )
+                .await?;
+            }
+            Matcher::LabelIds(keep_label_ids) => {
+                for keep_label_id in &keep_label_ids {
+                    let mut keep_label_ids = label_ids.to_vec();
+                    keep_label_ids.push(keep_label_id);
+                    mail::get_msg_ids_from_query(
+                        hub,
+                        &keep_label_ids,
+                        filter.clone(),
+                        "",
+                        msg_id_bts.clone(),
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        // A message protected for several reasons is counted under the first one
+        for msg_id in msg_id_bts.lock().await.iter().flatten() {
+            protected.entry(msg_id.clone()).or_insert(reason);
+        }
+    }
+    Ok(protected)
+}
+
+/// Inserts the ids of the messages carrying every label id in label_ids and matching the filter
+/// into the BTreeSet, leaving protected messages out through the list query itself and taking
+/// the ones with a keep label out by id. The protected messages are counted by why they're protected
+pub async fn add_unprotected_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_ids: &[&str],
+    filter: Option<Filter>,
+    msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) {
+    let Some(protection) = current() else {
+        mail::get_msg_ids_from_messages(hub, label_ids, filter, msg_id_bts).await;
+        return;
+    };
+
+    let protected = match list_protected(hub, &protection, label_ids, filter.clone()).await {
+        Ok(protected) => protected,
+        Err(e) => {
+            println!(
+                "Could not list the protected messages, so none of these messages are touched.\nError Received: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    let listed_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    let exclusion_query = protection.exclusion_query();
+    if let Err(e) =
+        mail::get_msg_ids_from_query(hub, label_ids, filter, &exclusion_query, listed_bts.clone())
+            .await
+    {
+        println!("{}", e);
+    }
+
+    {
+        let mut skipped = SKIPPED.lock().unwrap();
+        for (msg_id, reason) in &protected {
+            skipped.add_msg(msg_id, *reason);
+        }
+    }
+    let mut msg_id_bts_lock = msg_id_bts.lock().await;
+    for msg_id in listed_bts.lock().await.iter().flatten() {
+        if !protected.contains_key(msg_id) {
+            msg_id_bts_lock.insert(Some(msg_id.clone()));
+        }
+    }
+}
+
+/// Adds the message ids picked out by the by-msg-ids, by-labels or by-filter selector to the
+/// BTreeSet, leaving out protected messages and counting them by why they're protected
+pub async fn add_unprotected_msg_ids_from_opt(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    trash_opt: TrashOptions,
+    msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>>,
+) {
+    match trash_opt {
+        TrashOptions::ByMsgIds(msg_ids) => {
+            // Each given message is fetched to check that it exists, so it's checked right away
+            let protection = current();
+            for msg_id in msg_ids.msg_ids {
+                match check_msg_id(hub, protection.as_ref(), &msg_id).await {
+                    Ok(Some(reason)) => SKIPPED.lock().unwrap().add_msg(&msg_id, reason),
+                    Ok(None) => {
+                        msg_id_bts.lock().await.insert(Some(msg_id));
+                    }
+                    Err(_e) => println!("{} is a nonexistent message id", msg_id),
+                }
+            }
+        }
+        TrashOptions::ByLabels(labels) => {
+            for label in labels.labels {
+                let label_id = match mail::get_label_id(hub, &label).await {
+                    Ok(label_id) => label_id,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                add_unprotected_msg_ids(hub, &[&label_id], None, msg_id_bts.clone()).await;
+            }
+        }
+        TrashOptions::ByFilter(filter) => {
+            add_unprotected_msg_ids(hub, &[], Some(*filter), msg_id_bts).await;
+        }
+    }
+}
+
+/// Fetches a message's labels and sender, returning why it's protected if it is
+async fn check_msg_id(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    protection: Option<&Protection>,
+    msg_id: &str,
+) -> Result<Option<SkipReason>, Box<dyn std::error::Error>> {
+    let msg = mail::get_message_metadata(hub, msg_id, &["From"]).await?;
+    Ok(protection.and_then(|protection| protection.check(&msg)))
+}
+
+/// Filters out protected messages from ids that were picked out of the messages carrying every
+/// label id in label_ids and matching the filter, counting each one skipped by why it was
+/// protected. Only the protected messages among those are listed, through search queries rather
+/// than fetching every message. If they can't be listed, every message is skipped
+pub async fn unprotected_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_ids: &[&str],
+    filter: Option<Filter>,
+    msg_ids: &[String],
+) -> Vec<String> {
+    let Some(protection) = current() else {
+        return msg_ids.to_vec();
+    };
+    if msg_ids.is_empty() {
+        return Vec::new();
+    }
+
+    match list_protected(hub, &protection, label_ids, filter).await {
+        Ok(protected) => leave_out_protected(msg_ids, &protected),
+        Err(e) => skip_unchecked(msg_ids, e),
+    }
+}
+
+/// Filters out protected messages from ids that were picked out by the by-msg-ids, by-labels or
+/// by-filter selector, counting each one skipped by why it was protected. Only the protected
+/// messages the selector picks out are listed, and messages given by id are checked one at a time
+pub async fn unprotected_msg_ids_from_opt(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    trash_opt: &TrashOptions,
+    msg_ids: &[String],
+) -> Vec<String> {
+    let Some(protection) = current() else {
+        return msg_ids.to_vec();
+    };
+    if msg_ids.is_empty() {
+        return Vec::new();
+    }
+
+    let mut protected: BTreeMap<String, SkipReason> = BTreeMap::new();
+    match trash_opt {
+        TrashOptions::ByMsgIds(_msg_ids) => {
+            for msg_id in msg_ids {
+                let reason = match check_msg_id(hub, Some(&protection), msg_id).await {
+                    Ok(reason) => reason,
+                    Err(e) => {
+                        println!(
+                            "Could not check whether message with id {} is protected.\nError Received: {}",
+                            msg_id, e
+                        );
+                        Some(SkipReason::Unchecked)
+                    }
+                };
+                if let Some(reason) = reason {
+                    protected.insert(msg_id.clone(), reason);
+                }
+            }
+        }
+        TrashOptions::ByLabels(labels) => {
+            for label in &labels.labels {
+                // Labels that don't exist picked nothing out, so there's nothing to check
+                let Ok(label_id) = mail::get_label_id(hub, label).await else {
+                    continue;
+                };
+                match list_protected(hub, &protection, &[&label_id], None).await {
+                    Ok(label_protected) => {
+                        for (msg_id, reason) in label_protected {
+                            protected.entry(msg_id).or_insert(reason);
+                        }
+                    }
+                    Err(e) => return skip_unchecked(msg_ids, e),
+                }
+            }
+        }
+        TrashOptions::ByFilter(filter) => {
+            match list_protected(hub, &protection, &[], Some(*filter.clone())).await {
+                Ok(filter_protected) => protected = filter_protected,
+                Err(e) => return skip_unchecked(msg_ids, e),
+            }
+        }
+    }
+    leave_out_protected(msg_ids, &protected)
+}
+
+/// Returns the ids that aren't protected, counting each protected one by why it was protected
+fn leave_out_protected(
+    msg_ids: &[String],
+    protected: &BTreeMap<String, SkipReason>,
+) -> Vec<String> {
+    let mut skipped = SKIPPED.lock().unwrap();
+    msg_ids
+        .iter()
+        .filter(|msg_id| match protected.get(*msg_id) {
+            Some(reason) => {
+                skipped.add_msg(msg_id, *reason);
+                false
+            }
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Skips every message when the protected ones couldn't be listed, counting them as unchecked
+fn skip_unchecked(msg_ids: &[String], e: Box<dyn std::error::Error>) -> Vec<String> {
+    println!(
+        "Could not list the protected messages, so none of the {} messages are touched.\nError Received: {}",
+        msg_ids.len(),
+        e
+    );
+    SKIPPED.lock().unwrap().unchecked += msg_ids.len();
+    Vec::new()
+}
+
+/// Checks whether any message of the thread is protected, counting the thread as skipped if so
+pub async fn is_thread_protected(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    thread_id: &str,
+) -> bool {
+    let Some(protection) = current() else {
+        return false;
+    };
+
+    let result = hub
+        .users()
+        .threads_get("me", thread_id)
+        .format("metadata")
+        .add_metadata_headers("From")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await;
+
+    let reason = match result {
+        Ok((_res, thread)) => thread
+            .messages
+            .unwrap_or_default()
+            .iter()
+            .find_map(|msg| protection.check(msg)),
+        Err(e) => {
+            println!(
+                "Could not check whether thread with id {} is protected.\nError Received: {}",
+                thread_id, e
+            );
+            Some(SkipReason::Unchecked)
+        }
+    };
+
+    match reason {
+        Some(reason) => {
+            SKIPPED.lock().unwrap().add(reason);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_labels_are_matched_by_id_whatever_their_name() {
+        // The resolved id of a keep label like "Bills & Receipts (2024)" or "Überweisungen"
+        let protection = Protection {
+            starred: true,
+            important: false,
+            senders: vec!["boss@example.com".to_string()],
+            domains: Vec::new(),
+            keep_label_ids: vec!["Label_12".to_string()],
+        };
+
+        assert_eq!(
+            protection.exclusion_query(),
+            "-is:starred -from:boss@example.com"
+        );
+        assert!(protection.matchers().contains(&(
+            SkipReason::KeepLabel,
+            Matcher::LabelIds(vec!["Label_12".to_string()])
+        )));
+
+        let msg = Message {
+            label_ids: Some(vec!["INBOX".to_string(), "Label_12".to_string()]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            protection.check(&msg),
+            Some(SkipReason::KeepLabel)
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+extern crate google_gmail1 as gmail1;
+
+use chrono::{Duration, Local};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::TrashOptions;
+use crate::daemon;
+use crate::mail_service as mail;
+use crate::protect;
+
+/// Local state store holding when messages in watched labels were first seen read
+pub const READ_STATE_FILE: &str = "read_state.json";
+
+/// Which labels are watched, how far the mailbox history has been read, and when each
+/// message in a watched label lost its UNREAD label
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReadState {
+    /// History id the next sync starts from
+    pub history_id: Option<u64>,
+
+    /// Ids of the labels whose messages are tracked
+    pub watched_label_ids: BTreeSet<String>,
+
+    /// Message id to the unix timestamp of the sync that first saw it read
+    pub read_at: BTreeMap<String, i64>,
+}
+
+/// Reads the state store, starting from an empty one if it doesn't exist yet
+fn load_state() -> Result<ReadState, Box<dyn std::error::Error>> {
+    if !Path::new(READ_STATE_FILE).exists() {
+        return Ok(ReadState::default());
+    }
+
+    let state_string = fs::read_to_string(READ_STATE_FILE).map_err(|e| {
+        format!(
+            "Unable to read file {}.\nError Received: {}",
+            READ_STATE_FILE, e
+        )
+    })?;
+    let state = serde_json::from_str(&state_string)
+        .map_err(|e| format!("JSON was not well-formatted.\nError Received: {}", e))?;
+    Ok(state)
+}
+
+/// Writes the state store back to disk
+fn save_state(state: &ReadState) -> Result<(), Box<dyn std::error::Error>> {
+    let state_string = serde_json::to_string_pretty(state)?;
+    fs::write(READ_STATE_FILE, state_string).map_err(|e| {
+        format!(
+            "Unable to write file {}.\nError Received: {}",
+            READ_STATE_FILE, e
+        )
+    })?;
+    Ok(())
+}
+
+/// Fetches the mailbox's current history id, which history syncs start from
+async fn current_history_id(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let (_res, profile) = hub
+        .users()
+        .get_profile("me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await?;
+    Ok(profile
+        .history_id
+        .ok_or("The mailbox profile has no history id")?)
+}
+
+/// Starts (or stops) recording read times for messages within the given label names
+pub async fn watch_labels(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    label_names: &[String],
+    watch: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let label_ids = mail::get_label_ids(hub, label_names).await?;
+
+    let mut state = load_state()?;
+    for label_id in label_ids {
+        if watch {
+            state.watched_label_ids.insert(label_id);
+        } else {
+            state.watched_label_ids.remove(&label_id);
+        }
+    }
+
+    // Gmail's history can't be read back further than the moment watching starts
+    if state.history_id.is_none() {
+        state.history_id = Some(current_history_id(hub).await?);
+    }
+
+    save_state(&state)?;
+    println!(
+        "Watching {} labels for read messages",
+        state.watched_label_ids.len()
+    );
+    Ok(())
+}
+
+/// Whether any labels are watched, i.e. whether there's anything to sync
+pub fn is_watching() -> bool {
+    load_state().is_ok_and(|state| !state.watched_label_ids.is_empty())
+}
+
+/// Reads the mailbox history since the saved history id and records the current time for every
+/// message in a watched label that lost its UNREAD label since then. Messages that were
+/// permanently deleted are dropped from the store. Returns the updated state
+pub async fn sync(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<ReadState, Box<dyn std::error::Error>> {
+    let mut state = load_state()?;
+    if state.watched_label_ids.is_empty() {
+        return Err("No labels are watched yet, use the read-state watch command first".into());
+    }
+    let Some(start_history_id) = state.history_id else {
+        state.history_id = Some(current_history_id(hub).await?);
+        save_state(&state)?;
+        return Ok(state);
+    };
+
+    let now = Local::now().timestamp();
+    let mut recorded: usize = 0;
+    let mut next_history_id = start_history_id;
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut history_list = hub
+            .users()
+            .history_list("me")
+            .start_history_id(start_history_id)
+            .add_history_types("labelRemoved")
+            .add_history_types("messageDeleted")
+            .add_scope("https://mail.google.com/");
+        if let Some(ref page_token) = page_token {
+            history_list = history_list.page_token(page_token);
+        }
+
+        let history_page = match history_list.doit().await {
+            Ok((_res, history_page)) => history_page,
+            // Gmail only keeps about a week of history, so a stale history id has to start over
+            Err(gmail1::Error::BadRequest(e)) if e["error"]["code"] == 404 => {
+                println!(
+                    "Saved history id {} is too old, read times are recorded from now on.\nError Received: {}",
+                    start_history_id, e
+                );
+                state.history_id = Some(current_history_id(hub).await?);
+                save_state(&state)?;
+                return Ok(state);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        for history in history_page.history.unwrap_or_default() {
+            for label_removed in history.labels_removed.unwrap_or_default() {
+                let lost_unread = label_removed
+                    .label_ids
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|label_id| label_id == "UNREAD");
+                let Some(msg) = label_removed.message else {
+                    continue;
+                };
+                let watched = msg
+                    .label_ids
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|label_id| state.watched_label_ids.contains(label_id));
+
+                if let (true, true, Some(msg_id)) = (lost_unread, watched, msg.id) {
+                    if let Entry::Vacant(entry) = state.read_at.entry(msg_id) {
+                        entry.insert(now);
+                        recorded += 1;
+                    }
+                }
+            }
+
+            for msg_deleted in history.messages_deleted.unwrap_or_default() {
+                if let Some(msg_id) = msg_deleted.message.and_then(|msg| msg.id) {
+                    state.read_at.remove(&msg_id);
+                }
+            }
+        }
+
+        if let Some(history_id) = history_page.history_id {
+            next_history_id = history_id;
+        }
+        page_token = history_page.next_page_token;
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    state.history_id = Some(next_history_id);
+    save_state(&state)?;
+    println!("Recorded {} newly read messages", recorded);
+    Ok(state)
+}
+
+/// Syncs the state store, then picks out the selected messages that were first seen read
+/// longer ago than the given interval (e.g. 1d). Messages with no recorded read time and
+/// protected messages are left out
+pub async fn msg_ids_read_more_than(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    trash_opt: TrashOptions,
+    read_more_than: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let interval: Duration = daemon::parse_interval(read_more_than)?;
+    let state = sync(hub).await?;
+    let cutoff = (Local::now() - interval).timestamp();
+
+    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    protect::add_unprotected_msg_ids_from_opt(hub, trash_opt, msg_id_bts.clone()).await;
+    let selected: Vec<String> = msg_id_bts.lock().await.iter().flatten().cloned().collect();
+
+    let read_ids: Vec<String> = selected
+        .iter()
+        .filter(|msg_id| {
+            state
+                .read_at
+                .get(*msg_id)
+                .is_some_and(|read_at| *read_at <= cutoff)
+        })
+        .cloned()
+        .collect();
+
+    println!(
+        "{} of {} selected messages were read more than {} ago",
+        read_ids.len(),
+        selected.len(),
+        read_more_than
+    );
+    Ok(read_ids)
+}
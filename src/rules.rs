@@ -0,0 +1,495 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use serde::{self, de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::Filter;
+use crate::journal::LabelChanges;
+use crate::mail_service as mail;
+use crate::protect::{self, SkipReport};
+
+/// The default safety cap on how many messages a single delete rule may permanently delete
+const DEFAULT_DELETE_MAX_COUNT: usize = 1000;
+
+/// A rules file holding every retention rule to apply
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesFile {
+    pub rules: Vec<Rule>,
+}
+
+/// A retention rule: the messages it picks out (by filter and/or label names), how old they
+/// must be, and what to do with them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Name of the rule shown in the summary
+    pub name: String,
+
+    /// Messages matching this filter
+    #[serde(default)]
+    pub filter: Option<Filter>,
+
+    /// Messages within any of these label names
+    #[serde(default)]
+    pub labels: Option<Vec<String>>,
+
+    /// Minimum age of the messages in Gmail's older_than format (e.g. 1d, 2m, 1y)
+    #[serde(default)]
+    pub older_than: Option<String>,
+
+    /// What to do with the matched messages
+    pub action: RuleAction,
+
+    /// The most messages a delete rule may permanently delete before it's skipped (1000 by default)
+    #[serde(default)]
+    pub max_count: Option<usize>,
+}
+
+/// Actions a rule can take on the messages it matches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum RuleAction {
+    /// Move the messages into the trash
+    Trash,
+    /// Remove the messages from the inbox
+    Archive,
+    /// Add and remove label names on the messages
+    Label {
+        #[serde(default)]
+        add: Vec<String>,
+        #[serde(default)]
+        remove: Vec<String>,
+    },
+    /// Permanently delete the messages
+    Delete,
+}
+
+/// Outcome of applying a single rule
+pub struct RuleSummary {
+    pub name: String,
+    pub action: String,
+    pub matched: usize,
+    pub done: usize,
+    pub protected: usize,
+}
+
+impl RuleSummary {
+    /// Summarizes a rule from the messages collected for it, how many of those its action got
+    /// through, and the protected messages left out while collecting. Protected messages count
+    /// as matched, so the summary shows them next to the ones acted on
+    fn new(
+        rule: &Rule,
+        action: String,
+        collected: usize,
+        done: usize,
+        skipped: &SkipReport,
+    ) -> Self {
+        RuleSummary {
+            name: rule.name.clone(),
+            action,
+            matched: collected + skipped.total(),
+            done,
+            protected: skipped.total(),
+        }
+    }
+
+    /// One line description of what the rule matched and did
+    pub fn describe(&self) -> String {
+        if self.matched == 0 {
+            format!("{}: matched no messages", self.name)
+        } else {
+            format!(
+                "{}: matched {} messages, {} {}, {} protected, {} failed or skipped",
+                self.name,
+                self.matched,
+                self.done,
+                self.action,
+                self.protected,
+                self.matched
+                    .saturating_sub(self.done)
+                    .saturating_sub(self.protected)
+            )
+        }
+    }
+}
+
+/// Reads a config file, parsing it as JSON if it has a .json extension and as TOML otherwise
+pub fn read_toml_or_json<T: DeserializeOwned>(
+    config_file: &str,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let config_string = fs::read_to_string(config_file).map_err(|e| {
+        format!(
+            "Unable to read file {}.\nError Received: {}",
+            config_file, e
+        )
+    })?;
+
+    let config: T = match Path::new(config_file).extension() {
+        Some(ext) if ext == "json" => serde_json::from_str(&config_string)
+            .map_err(|e| format!("JSON was not well-formatted.\nError Received: {}", e))?,
+        _ => toml::from_str(&config_string)
+            .map_err(|e| format!("TOML was not well-formatted.\nError Received: {}", e))?,
+    };
+
+    Ok(config)
+}
+
+/// Reads a rules file, parsing it as JSON if it has a .json extension and as TOML otherwise
+pub fn load_rules(rules_file: &str) -> Result<Vec<Rule>, Box<dyn std::error::Error>> {
+    let rules: RulesFile = read_toml_or_json(rules_file)?;
+    validate_rules(&rules.rules)?;
+    Ok(rules.rules)
+}
+
+/// Checks that every rule can be applied safely
+pub fn validate_rules(rules: &[Rule]) -> Result<(), Box<dyn std::error::Error>> {
+    for rule in rules {
+        if rule.older_than.is_some() {
+            if let Some(filter) = &rule.filter {
+                if filter.txt.is_some() || filter.json.is_some() {
+                    return Err(format!(
+                        "Rule {} can't combine older_than with a txt or json filter file; put older_than in the file instead",
+                        rule.name
+                    )
+                    .into());
+                }
+            }
+        }
+
+        // A filter without any search terms (e.g. filter = {}) matches as much as no filter at all
+        let filter_query = match &rule.filter {
+            Some(filter) => mail::filter_query(filter)
+                .map_err(|e| format!("Rule {} has an unusable filter: {}", rule.name, e))?,
+            None => String::new(),
+        };
+        let no_labels = rule.labels.as_ref().is_none_or(|labels| labels.is_empty());
+        if filter_query.trim().is_empty() && no_labels {
+            return Err(format!(
+                "Rule {} needs a filter or labels, otherwise it would match every message",
+                rule.name
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the list passes a rule's messages are collected in, each the label ids a message has
+/// to carry, along with the filter every pass goes by
+async fn rule_passes(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    rule: &Rule,
+) -> (Vec<Vec<String>>, Option<Filter>) {
+    // The minimum age is added on top of the rule's filter, or makes up the whole filter when
+    // the rule only goes by labels
+    let mut filter = rule.filter.clone();
+    if let Some(older_than) = &rule.older_than {
+        filter.get_or_insert_with(Filter::default).older_than = Some(older_than.clone());
+    }
+
+    let passes = match &rule.labels {
+        Some(labels) => {
+            let mut passes = Vec::with_capacity(labels.len());
+            for label in labels {
+                match mail::get_label_id(hub, label).await {
+                    Ok(label_id) => passes.push(vec![label_id]),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            passes
+        }
+        // No labels means a single pass over the filter alone
+        None => vec![Vec::new()],
+    };
+    (passes, filter)
+}
+
+/// Collects the ids of every message the rule matches. Trash and delete rules leave out
+/// protected messages, counting them by why they're protected
+async fn collect_rule_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    rule: &Rule,
+) -> Vec<String> {
+    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+
+    let protected = matches!(rule.action, RuleAction::Trash | RuleAction::Delete);
+    let (passes, filter) = rule_passes(hub, rule).await;
+    for pass in &passes {
+        let label_ids: Vec<&str> = pass.iter().map(String::as_str).collect();
+        if protected {
+            protect::add_unprotected_msg_ids(hub, &label_ids, filter.clone(), msg_id_bts.clone())
+                .await;
+        } else {
+            mail::get_msg_ids_from_messages(hub, &label_ids, filter.clone(), msg_id_bts.clone())
+                .await;
+        }
+    }
+
+    let msg_id_bts_lock = msg_id_bts.lock().await;
+    msg_id_bts_lock.iter().flatten().cloned().collect()
+}
+
+/// Adds and removes label ids on the matched messages whose labels they actually change, leaving
+/// the rest alone. Which ones those are is found by going through the rule's passes again with
+/// each label id also required. Returns the number of messages that were modified
+async fn modify_changed_msg_ids(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    rule: &Rule,
+    msg_ids: &[String],
+    add_label_ids: &[String],
+    remove_label_ids: &[String],
+) -> usize {
+    let msg_ids: BTreeSet<String> = msg_ids.iter().cloned().collect();
+    let (passes, filter) = rule_passes(hub, rule).await;
+    let carrying = mail::selection_carrying(
+        hub,
+        &msg_ids,
+        &passes,
+        filter,
+        add_label_ids.iter().chain(remove_label_ids),
+        false,
+    )
+    .await;
+    let changes = LabelChanges::new(&msg_ids, add_label_ids, remove_label_ids, &carrying);
+    let changed_ids: Vec<String> = changes.changed_ids().into_iter().collect();
+    mail::modify_msg_ids(hub, &changed_ids, &changes).await
+}
+
+/// Applies a single rule's action on the matched messages.
+/// Returns a description of the action and how many messages it went through on
+async fn apply_rule_action(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    rule: &Rule,
+    msg_ids: &[String],
+    yes: bool,
+) -> (String, usize) {
+    match &rule.action {
+        RuleAction::Trash => (
+            "trashed".to_string(),
+            mail::trash_msg_ids(hub, msg_ids).await,
+        ),
+        RuleAction::Archive => (
+            "archived".to_string(),
+            modify_changed_msg_ids(hub, rule, msg_ids, &[], &["INBOX".to_string()]).await,
+        ),
+        RuleAction::Label { add, remove } => {
+            let add_label_ids = match mail::get_label_ids(hub, add).await {
+                Ok(label_ids) => label_ids,
+                Err(e) => {
+                    println!("{}", e);
+                    return ("relabeled".to_string(), 0);
+                }
+            };
+            let remove_label_ids = match mail::get_label_ids(hub, remove).await {
+                Ok(label_ids) => label_ids,
+                Err(e) => {
+                    println!("{}", e);
+                    return ("relabeled".to_string(), 0);
+                }
+            };
+            (
+                "relabeled".to_string(),
+                modify_changed_msg_ids(hub, rule, msg_ids, &add_label_ids, &remove_label_ids).await,
+            )
+        }
+        RuleAction::Delete => {
+            // Protected messages were left out when the msg ids were collected, before the
+            // count is checked or confirmed
+            let max_count = rule.max_count.unwrap_or(DEFAULT_DELETE_MAX_COUNT);
+            if msg_ids.len() > max_count {
+                println!(
+                    "Skipping rule {}: refusing to permanently delete {} messages, which is more than its max count of {}",
+                    rule.name,
+                    msg_ids.len(),
+                    max_count
+                );
+                return ("permanently deleted".to_string(), 0);
+            }
+
+            if !yes
+                && !crate::confirm(&format!(
+                    "Rule {} will permanently delete {} messages. This cannot be undone, continue?",
+                    rule.name,
+                    msg_ids.len()
+                ))
+            {
+                println!("Skipping rule {}", rule.name);
+                return ("permanently deleted".to_string(), 0);
+            }
+
+            (
+                "permanently deleted".to_string(),
+                mail::delete_msgs(hub, msg_ids).await,
+            )
+        }
+    }
+}
+
+/// Applies every rule in order within the same authenticated session.
+/// Returns a summary of how many messages each rule matched and acted on.
+/// On a dry run the matched messages of each rule are only listed
+pub async fn apply_rules(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    rules: &[Rule],
+    yes: bool,
+    dry_run: bool,
+    dry_run_output: &Option<String>,
+) -> Vec<RuleSummary> {
+    let mut summaries: Vec<RuleSummary> = Vec::with_capacity(rules.len());
+
+    for rule in rules {
+        println!("Applying rule {}", rule.name);
+        let msg_ids = collect_rule_msg_ids(hub, rule).await;
+        let skipped = protect::take_skipped();
+        skipped.print("messages");
+
+        if dry_run {
+            if let Some(output_file) = dry_run_output {
+                mail::append_to_output(output_file, &format!("Rule: {}\n\n", rule.name));
+            }
+            mail::preview_msg_ids(hub, &msg_ids, dry_run_output).await;
+            summaries.push(RuleSummary::new(
+                rule,
+                "listed (dry run)".to_string(),
+                msg_ids.len(),
+                msg_ids.len(),
+                &skipped,
+            ));
+            continue;
+        }
+
+        let (action, done) = if msg_ids.is_empty() {
+            (String::new(), 0)
+        } else {
+            apply_rule_action(hub, rule, &msg_ids, yes).await
+        };
+
+        summaries.push(RuleSummary::new(
+            rule,
+            action,
+            msg_ids.len(),
+            done,
+            &skipped,
+        ));
+    }
+
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_rules(toml_string: &str) -> Vec<Rule> {
+        toml::from_str::<RulesFile>(toml_string).unwrap().rules
+    }
+
+    #[test]
+    fn accepts_rules_with_a_filter_or_labels() {
+        let rules = parse_rules(
+            r#"
+            [[rules]]
+            name = "old newsletters"
+            labels = ["Newsletters"]
+            older_than = "1y"
+            action = "trash"
+
+            [[rules]]
+            name = "receipts"
+            filter = { from = ["receipts@example.com"] }
+            older_than = "6m"
+            action = { label = { add = ["Receipts"] } }
+            "#,
+        );
+        assert!(validate_rules(&rules).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_rule_matching_every_message() {
+        let rules = parse_rules(
+            r#"
+            [[rules]]
+            name = "everything"
+            older_than = "1y"
+            action = "delete"
+            "#,
+        );
+        let error = validate_rules(&rules).unwrap_err().to_string();
+        assert!(error.contains("everything"));
+    }
+
+    #[test]
+    fn rejects_a_filter_without_search_terms() {
+        for filter in ["{}", r#"{ from = [], words = [" "] }"#] {
+            let rules = parse_rules(&format!(
+                "[[rules]]\nname = \"empty filter\"\nfilter = {}\nolder_than = \"1y\"\naction = \"trash\"",
+                filter
+            ));
+            assert!(validate_rules(&rules).is_err(), "{}", filter);
+        }
+
+        // Empty labels don't narrow anything down either
+        let rules = parse_rules(
+            r#"
+            [[rules]]
+            name = "no labels"
+            labels = []
+            filter = {}
+            action = "delete"
+            "#,
+        );
+        assert!(validate_rules(&rules).is_err());
+    }
+
+    #[test]
+    fn rejects_older_than_with_a_filter_file() {
+        for filter in [r#"{ txt = "query.txt" }"#, r#"{ json = "filter.json" }"#] {
+            let rules = parse_rules(&format!(
+                "[[rules]]\nname = \"from file\"\nfilter = {}\nolder_than = \"30d\"\naction = \"archive\"",
+                filter
+            ));
+            assert!(validate_rules(&rules).is_err());
+        }
+    }
+
+    #[test]
+    fn summaries_count_protected_messages_as_matched() {
+        let rule = &parse_rules(
+            r#"
+            [[rules]]
+            name = "old mail"
+            labels = ["Old"]
+            action = "trash"
+            "#,
+        )[0];
+        // Two messages were left out as protected while collecting, then one of the three
+        // collected couldn't be trashed
+        let mut skipped = SkipReport::default();
+        skipped.starred = 1;
+        skipped.sender = 1;
+        let summary = RuleSummary::new(rule, "trashed".to_string(), 3, 2, &skipped);
+        assert_eq!(
+            summary.describe(),
+            "old mail: matched 5 messages, 2 trashed, 2 protected, 1 failed or skipped"
+        );
+
+        let summary = RuleSummary::new(rule, "listed (dry run)".to_string(), 3, 3, &skipped);
+        assert_eq!(
+            summary.describe(),
+            "old mail: matched 5 messages, 3 listed (dry run), 2 protected, 0 failed or skipped"
+        );
+
+        let summary = RuleSummary::new(rule, String::new(), 0, 0, &SkipReport::default());
+        assert_eq!(summary.describe(), "old mail: matched no messages");
+    }
+}
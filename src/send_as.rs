@@ -0,0 +1,214 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::api::SendAs;
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use pulldown_cmark::{html, Parser};
+use std::fs;
+use std::path::Path;
+
+use crate::cmd_args::{SendAsDefault, SendAsReplyTo, SendAsSignature};
+
+/// Lists every send-as alias of the authenticated email
+async fn list_aliases(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<Vec<SendAs>, Box<dyn std::error::Error>> {
+    let (_res, send_as_list) = hub
+        .users()
+        .settings_send_as_list("me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await
+        .map_err(|e| format!("Could not list send-as aliases.\nError Received: {}", e))?;
+    Ok(send_as_list.send_as.unwrap_or_default())
+}
+
+/// Picks the email addresses of the given aliases (in any case), or of every alias when
+/// all_aliases is set. Errors on an address that isn't an alias
+async fn pick_aliases(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    emails: &[String],
+    all_aliases: bool,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let aliases: Vec<String> = list_aliases(hub)
+        .await?
+        .into_iter()
+        .filter_map(|alias| alias.send_as_email)
+        .collect();
+    if all_aliases {
+        return Ok(aliases);
+    }
+
+    let mut picked = Vec::with_capacity(emails.len());
+    for email in emails {
+        let Some(alias) = aliases
+            .iter()
+            .find(|alias| alias.eq_ignore_ascii_case(email))
+        else {
+            return Err(format!(
+                "{} is a nonexistent send-as alias. Aliases: {}",
+                email,
+                aliases.join(", ")
+            )
+            .into());
+        };
+        picked.push(alias.clone());
+    }
+    Ok(picked)
+}
+
+/// Patches the given fields of every alias, reporting each one. On a dry run nothing is changed
+async fn patch_aliases(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    aliases: &[String],
+    patch: SendAs,
+    change: &str,
+    dry_run: bool,
+) {
+    let mut updated: usize = 0;
+    for alias in aliases {
+        if dry_run {
+            println!("Dry run: would {}: {}", change, alias);
+            continue;
+        }
+        match hub
+            .users()
+            .settings_send_as_patch(patch.clone(), "me", alias)
+            .add_scope("https://mail.google.com/")
+            .doit()
+            .await
+        {
+            Ok(_res) => {
+                println!("Updated {}", alias);
+                updated += 1;
+            }
+            Err(e) => println!(
+                "Could not update send-as alias {}.\nError Received: {}",
+                alias, e
+            ),
+        }
+    }
+    if !dry_run {
+        println!("\nUpdated {} of {} aliases", updated, aliases.len());
+    }
+}
+
+/// Prints every send-as alias with its display name, reply-to address and signature
+pub async fn print_aliases(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for alias in list_aliases(hub).await? {
+        let mut flags: Vec<&str> = Vec::new();
+        if alias.is_primary == Some(true) {
+            flags.push("primary");
+        }
+        if alias.is_default == Some(true) {
+            flags.push("default");
+        }
+        if let Some(verification_status) = alias.verification_status.as_deref() {
+            flags.push(verification_status);
+        }
+
+        println!(
+            "{}{}",
+            alias.send_as_email.unwrap_or_default(),
+            if flags.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", flags.join(", "))
+            }
+        );
+        if let Some(display_name) = alias.display_name.filter(|name| !name.is_empty()) {
+            println!("    Display name: {}", display_name);
+        }
+        if let Some(reply_to) = alias
+            .reply_to_address
+            .filter(|reply_to| !reply_to.is_empty())
+        {
+            println!("    Reply-To: {}", reply_to);
+        }
+        match alias.signature.filter(|signature| !signature.is_empty()) {
+            Some(signature) => println!(
+                "    Signature:\n        {}",
+                signature.replace('\n', "\n        ")
+            ),
+            None => println!("    No signature"),
+        }
+    }
+    Ok(())
+}
+
+/// Reads a signature file, rendering it to HTML if it's Markdown (a .md or .markdown extension)
+fn read_signature(file: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let signature = fs::read_to_string(file)
+        .map_err(|e| format!("Unable to read file {}.\nError Received: {}", file, e))?;
+
+    let is_markdown = Path::new(file)
+        .extension()
+        .is_some_and(|ext| ext == "md" || ext == "markdown");
+    if !is_markdown {
+        return Ok(signature);
+    }
+    let mut signature_html = String::new();
+    html::push_html(&mut signature_html, Parser::new(&signature));
+    Ok(signature_html)
+}
+
+/// Sets the signature of the given aliases, or of every alias, from an HTML or Markdown file
+pub async fn set_signature(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    signature: &SendAsSignature,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let signature_html = read_signature(&signature.file)?;
+    let aliases = pick_aliases(hub, &signature.email, signature.all_aliases).await?;
+    if dry_run {
+        println!("Signature:\n{}", signature_html);
+    }
+
+    let patch = SendAs {
+        signature: Some(signature_html),
+        ..Default::default()
+    };
+    patch_aliases(hub, &aliases, patch, "set the signature", dry_run).await;
+    Ok(())
+}
+
+/// Makes an alias the default "From:" address
+pub async fn set_default(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    default: &SendAsDefault,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let aliases = pick_aliases(hub, std::slice::from_ref(&default.email), false).await?;
+    let patch = SendAs {
+        is_default: Some(true),
+        ..Default::default()
+    };
+    patch_aliases(hub, &aliases, patch, "make the default alias", dry_run).await;
+    Ok(())
+}
+
+/// Sets (or clears) the reply-to address of the given aliases, or of every alias
+pub async fn set_reply_to(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    reply_to: &SendAsReplyTo,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let aliases = pick_aliases(hub, &reply_to.email, reply_to.all_aliases).await?;
+
+    // An empty address makes Gmail stop adding a Reply-To header
+    let address = reply_to.address.clone().unwrap_or_default();
+    let change = if address.is_empty() {
+        "clear the reply-to address".to_string()
+    } else {
+        format!("set the reply-to address to {}", address)
+    };
+    let patch = SendAs {
+        reply_to_address: Some(address),
+        ..Default::default()
+    };
+    patch_aliases(hub, &aliases, patch, &change, dry_run).await;
+    Ok(())
+}
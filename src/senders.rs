@@ -0,0 +1,266 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::sync::Arc;
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::{Filter, Senders};
+use crate::journal::LabelChanges;
+use crate::mail_service as mail;
+use crate::protect;
+
+/// Every matched message from one sender address or domain
+#[derive(Default)]
+struct SenderStats {
+    msg_ids: Vec<String>,
+    total_size: u64,
+}
+
+/// A row of a senders table: who it is and their stats
+struct Row<'a> {
+    label: String,
+    name: &'a str,
+    stats: &'a SenderStats,
+}
+
+/// Sorts senders by message count, then total size, most first, keeping the top ones
+fn top_rows<'a>(
+    senders: &'a BTreeMap<String, SenderStats>,
+    top: usize,
+    prefix: &str,
+) -> Vec<Row<'a>> {
+    let mut sorted: Vec<(&String, &SenderStats)> = senders.iter().collect();
+    sorted.sort_by(|(_, a), (_, b)| {
+        (b.msg_ids.len(), b.total_size).cmp(&(a.msg_ids.len(), a.total_size))
+    });
+    sorted
+        .into_iter()
+        .take(top)
+        .enumerate()
+        .map(|(index, (name, stats))| Row {
+            label: format!("{}{}", prefix, index + 1),
+            name,
+            stats,
+        })
+        .collect()
+}
+
+/// Prints a table of senders with their row label, message count and total size
+fn print_table(title: &str, rows: &[Row]) {
+    println!("\n{}", title);
+    println!("{:>5}  {:>8}  {:>10}  Sender", "Row", "Messages", "Size");
+    for row in rows {
+        println!(
+            "{:>5}  {:>8}  {:>10}  {}",
+            row.label,
+            row.stats.msg_ids.len(),
+            mail::format_size(row.stats.total_size),
+            row.name
+        );
+    }
+}
+
+/// Reads a line from stdin after printing the prompt
+fn prompt(prompt: &str) -> String {
+    print!("{}", prompt);
+    io::stdout().flush().expect("Could not flush stdout");
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return String::new();
+    }
+    answer.trim().to_string()
+}
+
+/// Whether every message of the row was already trashed or labeled
+fn row_acted_on(row: &Row<'_>, acted_msg_ids: &BTreeSet<String>) -> bool {
+    row.stats
+        .msg_ids
+        .iter()
+        .all(|msg_id| acted_msg_ids.contains(msg_id))
+}
+
+/// Lets the user pick rows from the tables and trash or label every message of the picked senders.
+/// carried holds the label ids each scanned message carries, and is kept up to date as labels are added.
+/// Messages are only acted on once, so a row whose messages were all trashed or labeled already
+/// can't be picked again
+async fn pick_rows(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    filter: &Filter,
+    rows: &[Row<'_>],
+    carried: &mut BTreeMap<String, Vec<String>>,
+    dry_run: bool,
+) {
+    let mut acted_msg_ids: BTreeSet<String> = BTreeSet::new();
+    loop {
+        let picked =
+            prompt("\nRows to act on, separated by commas (e.g. 1,3,D2), or nothing to finish: ");
+        if picked.is_empty() {
+            return;
+        }
+
+        let mut msg_ids: BTreeSet<String> = BTreeSet::new();
+        let mut unknown: Vec<&str> = Vec::new();
+        let mut acted_rows: Vec<&str> = Vec::new();
+        for label in picked
+            .split(',')
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+        {
+            match rows
+                .iter()
+                .find(|row| row.label.eq_ignore_ascii_case(label))
+            {
+                Some(row) if row_acted_on(row, &acted_msg_ids) => acted_rows.push(label),
+                Some(row) => msg_ids.extend(row.stats.msg_ids.iter().cloned()),
+                None => unknown.push(label),
+            }
+        }
+        if !unknown.is_empty() {
+            println!("{} are nonexistent rows", unknown.join(", "));
+            continue;
+        }
+        if !acted_rows.is_empty() {
+            println!(
+                "The messages of {} were already trashed or labeled",
+                acted_rows.join(", ")
+            );
+            continue;
+        }
+        // Rows can share messages (an address and its domain), and those are only acted on once
+        let msg_ids: Vec<String> = msg_ids.difference(&acted_msg_ids).cloned().collect();
+
+        let action = prompt("Action (trash, or label followed by label names): ");
+        let mut words = action.split_whitespace();
+        match words.next() {
+            Some("trash") => {
+                let unprotected =
+                    protect::unprotected_msg_ids(hub, &[], Some(filter.clone()), &msg_ids).await;
+                let skipped = protect::take_skipped();
+                if dry_run {
+                    println!("Dry run: {} messages would be trashed", unprotected.len());
+                    skipped.print("messages");
+                    continue;
+                }
+                let trashed = mail::trash_msg_ids(hub, &unprotected).await;
+                if trashed < unprotected.len() {
+                    println!("Could not trash {} messages", unprotected.len() - trashed);
+                }
+                println!("Trashed {} messages!", trashed);
+                skipped.print("messages");
+                // Protected messages count as acted on too, since trashing them again would only skip them again
+                acted_msg_ids.extend(msg_ids);
+            }
+            Some("label") => {
+                let label_names: Vec<String> = words.map(str::to_string).collect();
+                if label_names.is_empty() {
+                    println!("Give the label names to add after label");
+                    continue;
+                }
+                let label_ids = match mail::get_label_ids(hub, &label_names).await {
+                    Ok(label_ids) => label_ids,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                if dry_run {
+                    println!("Dry run: {} messages would be labeled", msg_ids.len());
+                    continue;
+                }
+                // Messages already carrying a label are left out of its journal entry
+                let picked_carried: BTreeMap<String, Vec<String>> = msg_ids
+                    .iter()
+                    .map(|msg_id| {
+                        let label_ids = carried.get(msg_id).cloned().unwrap_or_default();
+                        (msg_id.clone(), label_ids)
+                    })
+                    .collect();
+                let changes = LabelChanges::from_carried(&picked_carried, &label_ids, &[]);
+                let labeled = mail::modify_msg_ids(hub, &msg_ids, &changes).await;
+                for msg_id in &msg_ids {
+                    let msg_label_ids = carried.entry(msg_id.clone()).or_default();
+                    for label_id in &label_ids {
+                        if !msg_label_ids.contains(label_id) {
+                            msg_label_ids.push(label_id.clone());
+                        }
+                    }
+                }
+                if labeled < msg_ids.len() {
+                    println!("Could not label {} messages", msg_ids.len() - labeled);
+                }
+                println!("Labeled {} messages!", labeled);
+                acted_msg_ids.extend(msg_ids);
+            }
+            _ => println!("Unknown action {}", action),
+        }
+    }
+}
+
+/// Scans the messages matched by the filter with metadata-only fetches and prints the senders
+/// and sender domains with the most messages, along with their total size. With interactive set,
+/// the user can then pick rows whose messages get trashed or labeled
+pub async fn senders_report(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    senders: &Senders,
+    dry_run: bool,
+) {
+    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    mail::get_msg_ids_from_messages(hub, &[], Some(senders.filter.clone()), msg_id_bts.clone())
+        .await;
+    let msg_ids: Vec<String> = msg_id_bts.lock().await.iter().flatten().cloned().collect();
+    if msg_ids.is_empty() {
+        println!("No messages matched");
+        return;
+    }
+    println!("Scanning {} messages", msg_ids.len());
+
+    let mut by_address: BTreeMap<String, SenderStats> = BTreeMap::new();
+    let mut by_domain: BTreeMap<String, SenderStats> = BTreeMap::new();
+    let mut carried: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for msg_id in msg_ids {
+        let msg = match mail::get_message_metadata(hub, &msg_id, &["From"]).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                println!(
+                    "Could not read message with id {}.\nError Received: {}",
+                    msg_id, e
+                );
+                continue;
+            }
+        };
+
+        let address = mail::sender_address(&mail::get_header(&msg, "From").unwrap_or_default());
+        let domain = address
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_string())
+            .unwrap_or(address.clone());
+        let size = msg.size_estimate.unwrap_or(0).max(0) as u64;
+        carried.insert(msg_id.clone(), msg.label_ids.clone().unwrap_or_default());
+
+        for (senders, name) in [(&mut by_address, address), (&mut by_domain, domain)] {
+            let stats = senders.entry(name).or_default();
+            stats.msg_ids.push(msg_id.clone());
+            stats.total_size += size;
+        }
+    }
+
+    let address_rows = top_rows(&by_address, senders.top, "");
+    let domain_rows = top_rows(&by_domain, senders.top, "D");
+    print_table("Top senders by address", &address_rows);
+    print_table("Top senders by domain", &domain_rows);
+
+    if senders.interactive {
+        if let Err(e) = protect::load(hub).await {
+            println!("{}", e);
+            return;
+        }
+        let rows: Vec<Row> = address_rows.into_iter().chain(domain_rows).collect();
+        pick_rows(hub, &senders.filter, &rows, &mut carried, dry_run).await;
+    }
+}
@@ -0,0 +1,223 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::api::{Message, MessagePart};
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::{Filter, Storage};
+use crate::label_registry;
+use crate::mail_service as mail;
+use crate::protect;
+
+/// A large message: its size, sender and labels, and the attachments making it large
+struct LargeMessage {
+    msg_id: String,
+    size: u64,
+    sender: String,
+    subject: String,
+    label_ids: Vec<String>,
+    attachments: Vec<(String, u64)>,
+}
+
+/// Collects the filename and size of every attachment in a message part and the parts nested in it
+fn collect_attachments(part: &MessagePart, attachments: &mut Vec<(String, u64)>) {
+    if let Some(filename) = part
+        .filename
+        .as_ref()
+        .filter(|filename| !filename.is_empty())
+    {
+        let size = part
+            .body
+            .as_ref()
+            .and_then(|body| body.size)
+            .unwrap_or(0)
+            .max(0) as u64;
+        attachments.push((filename.clone(), size));
+    }
+    for part in part.parts.iter().flatten() {
+        collect_attachments(part, attachments);
+    }
+}
+
+/// Fetches the full message to list its attachments
+async fn attachments_of(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    msg_id: &str,
+) -> Vec<(String, u64)> {
+    let mut attachments = Vec::new();
+    match mail::get_message(hub, msg_id).await {
+        Ok(Message {
+            payload: Some(payload),
+            ..
+        }) => collect_attachments(&payload, &mut attachments),
+        Ok(_msg) => {}
+        Err(e) => println!(
+            "Could not read attachments of message with id {}.\nError Received: {}",
+            msg_id, e
+        ),
+    }
+    attachments
+}
+
+/// Prints the mailbox totals from the user's profile
+async fn print_profile(hub: &Gmail<HttpsConnector<HttpConnector>>) {
+    let result = hub
+        .users()
+        .get_profile("me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await;
+    match result {
+        Ok((_res, profile)) => println!(
+            "Mailbox {}: {} messages in {} threads",
+            profile.email_address.unwrap_or_default(),
+            profile.messages_total.unwrap_or(0),
+            profile.threads_total.unwrap_or(0)
+        ),
+        Err(e) => println!("Could not read mailbox profile.\nError Received: {}", e),
+    }
+}
+
+/// Prints how much of the listed messages' size falls under each group, largest first
+fn print_groups(title: &str, groups: BTreeMap<String, (usize, u64)>) {
+    let mut groups: Vec<(String, (usize, u64))> = groups.into_iter().collect();
+    groups.sort_by(|(_, (_, a)), (_, (_, b))| b.cmp(a));
+
+    println!("\n{}", title);
+    println!("{:>10}  {:>8}  Name", "Size", "Messages");
+    for (name, (count, size)) in groups {
+        println!("{:>10}  {:>8}  {}", mail::format_size(size), count, name);
+    }
+}
+
+/// Reports the mailbox totals and the largest messages (above the scan threshold) with their
+/// attachments, grouped by label and by sender. With trash set, the listed messages are then
+/// trashed (protected messages are skipped); on a dry run they are only reported
+pub async fn storage_report(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    storage: &Storage,
+    dry_run: bool,
+) {
+    print_profile(hub).await;
+
+    // Only messages above the threshold are scanned, since every scanned message needs a fetch
+    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    let filter = Filter {
+        larger: Some(storage.larger),
+        ..Default::default()
+    };
+    mail::get_msg_ids_from_messages(hub, &[], Some(filter.clone()), msg_id_bts.clone()).await;
+    let msg_ids: Vec<String> = msg_id_bts.lock().await.iter().flatten().cloned().collect();
+    if msg_ids.is_empty() {
+        println!(
+            "No messages larger than {}",
+            mail::format_size(storage.larger as u64)
+        );
+        return;
+    }
+    println!(
+        "Scanning {} messages larger than {}",
+        msg_ids.len(),
+        mail::format_size(storage.larger as u64)
+    );
+
+    let mut messages: Vec<LargeMessage> = Vec::with_capacity(msg_ids.len());
+    for msg_id in msg_ids {
+        match mail::get_message_metadata(hub, &msg_id, &["From", "Subject"]).await {
+            Ok(msg) => messages.push(LargeMessage {
+                size: msg.size_estimate.unwrap_or(0).max(0) as u64,
+                sender: mail::sender_address(&mail::get_header(&msg, "From").unwrap_or_default()),
+                subject: mail::get_header(&msg, "Subject").unwrap_or_default(),
+                label_ids: msg.label_ids.unwrap_or_default(),
+                attachments: Vec::new(),
+                msg_id,
+            }),
+            Err(e) => println!(
+                "Could not read message with id {}.\nError Received: {}",
+                msg_id, e
+            ),
+        }
+    }
+    messages.sort_by_key(|msg| Reverse(msg.size));
+    messages.truncate(storage.top);
+
+    for msg in messages.iter_mut() {
+        msg.attachments = attachments_of(hub, &msg.msg_id).await;
+    }
+
+    let total_size: u64 = messages.iter().map(|msg| msg.size).sum();
+    println!(
+        "\nLargest {} messages ({} in total)",
+        messages.len(),
+        mail::format_size(total_size)
+    );
+    for (index, msg) in messages.iter().enumerate() {
+        println!(
+            "{:>3}. {:>10}  {}  {}\n     Message ID: {}",
+            index + 1,
+            mail::format_size(msg.size),
+            msg.sender,
+            msg.subject,
+            msg.msg_id
+        );
+        for (filename, size) in &msg.attachments {
+            println!("     - {} ({})", filename, mail::format_size(*size));
+        }
+    }
+
+    let mut by_label: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    let mut by_sender: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for msg in &messages {
+        for label_id in &msg.label_ids {
+            let label_name = label_registry::label_name(hub, label_id)
+                .await
+                .unwrap_or(label_id.clone());
+            let group = by_label.entry(label_name).or_default();
+            group.0 += 1;
+            group.1 += msg.size;
+        }
+        let group = by_sender.entry(msg.sender.clone()).or_default();
+        group.0 += 1;
+        group.1 += msg.size;
+    }
+    print_groups("By label", by_label);
+    print_groups("By sender", by_sender);
+
+    if !storage.trash {
+        return;
+    }
+    if let Err(e) = protect::load(hub).await {
+        println!("{}", e);
+        return;
+    }
+    let msg_ids: Vec<String> = messages.iter().map(|msg| msg.msg_id.clone()).collect();
+    let msg_ids = protect::unprotected_msg_ids(hub, &[], Some(filter), &msg_ids).await;
+    let skipped = protect::take_skipped();
+    if dry_run {
+        let unprotected_size: u64 = messages
+            .iter()
+            .filter(|msg| msg_ids.contains(&msg.msg_id))
+            .map(|msg| msg.size)
+            .sum();
+        println!(
+            "\nDry run: {} messages adding up to {} would be trashed",
+            msg_ids.len(),
+            mail::format_size(unprotected_size)
+        );
+        skipped.print("messages");
+        return;
+    }
+
+    let trashed = mail::trash_msg_ids(hub, &msg_ids).await;
+    if trashed < msg_ids.len() {
+        println!("Could not trash {} messages", msg_ids.len() - trashed);
+    }
+    println!("\nTrashed {} messages!", trashed);
+    skipped.print("messages");
+}
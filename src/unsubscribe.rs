@@ -0,0 +1,397 @@
+extern crate google_gmail1 as gmail1;
+
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::{hyper, hyper_rustls, Gmail};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex as tokio_mutex;
+
+use crate::cmd_args::{Send, Unsubscribe};
+use crate::mail_service as mail;
+
+/// How long a one-click unsubscribe POST may take before the list counts as failed
+const ONE_CLICK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a list is unsubscribed from
+enum Method {
+    /// RFC 8058 one-click POST to an https URL
+    OneClick(String),
+    /// Email to the list's unsubscribe address
+    Mailto(String),
+    /// Only a web page that has to be visited by hand
+    Manual(String),
+}
+
+/// A mailing list (or a sender without a List-Id) seen in the matched messages and the
+/// unsubscribe headers of the first message from it
+struct MailingList {
+    name: String,
+    messages: usize,
+    unsubscribe: Option<String>,
+    one_click: bool,
+}
+
+/// Outcome of unsubscribing from a single list
+struct Attempt {
+    list: String,
+    method: String,
+    result: String,
+    succeeded: bool,
+}
+
+/// Splits a List-Unsubscribe header like "<mailto:a@b.com?subject=x>, <https://b.com/u>"
+/// into its URIs
+fn unsubscribe_uris(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .filter_map(|part| {
+            let part = part.trim();
+            part.strip_prefix('<')
+                .and_then(|part| part.strip_suffix('>'))
+                .map(|uri| uri.trim().to_string())
+        })
+        .collect()
+}
+
+/// Picks how to unsubscribe from a list, preferring a one-click POST, then a mailto,
+/// then a web page that has to be visited by hand
+fn pick_method(list: &MailingList) -> Option<Method> {
+    let uris = unsubscribe_uris(list.unsubscribe.as_deref()?);
+    let https_uri = uris.iter().find(|uri| uri.starts_with("https://"));
+
+    if let (Some(https_uri), true) = (https_uri, list.one_click) {
+        return Some(Method::OneClick(https_uri.clone()));
+    }
+    if let Some(mailto) = uris.iter().find(|uri| uri.starts_with("mailto:")) {
+        return Some(Method::Mailto(mailto.clone()));
+    }
+    uris.iter()
+        .find(|uri| uri.starts_with("http://") || uri.starts_with("https://"))
+        .map(|uri| Method::Manual(uri.clone()))
+}
+
+/// Decodes %XX escapes in a mailto address or query value
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Splits a mailto URI into its address, subject and body
+fn parse_mailto(mailto: &str) -> (String, Option<String>, Option<String>) {
+    let mailto = mailto.trim_start_matches("mailto:");
+    let (address, query) = mailto.split_once('?').unwrap_or((mailto, ""));
+    let mut subject = None;
+    let mut body = None;
+    for pair in query.split('&') {
+        match pair.split_once('=') {
+            Some((key, value)) if key.eq_ignore_ascii_case("subject") => {
+                subject = Some(percent_decode(value))
+            }
+            Some((key, value)) if key.eq_ignore_ascii_case("body") => {
+                body = Some(percent_decode(value))
+            }
+            _ => {}
+        }
+    }
+    (percent_decode(address), subject, body)
+}
+
+/// Sends the RFC 8058 one-click unsubscribe POST, giving up after ONE_CLICK_TIMEOUT.
+/// Returns the response status on success
+async fn one_click_post(url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()?
+        .https_only()
+        .enable_http1()
+        .build();
+    let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build(https);
+
+    let request = hyper::Request::post(url)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(hyper::Body::from("List-Unsubscribe=One-Click"))?;
+    let response = tokio::time::timeout(ONE_CLICK_TIMEOUT, client.request(request))
+        .await
+        .map_err(|_| {
+            format!(
+                "Server didn't respond within {} seconds",
+                ONE_CLICK_TIMEOUT.as_secs()
+            )
+        })??;
+
+    if response.status().is_success() {
+        Ok(response.status().to_string())
+    } else {
+        Err(format!("Server responded with {}", response.status()).into())
+    }
+}
+
+/// Sends the unsubscribe email through the same SMTP path as the send command
+async fn send_mailto(
+    unsubscribe: &Unsubscribe,
+    mailto: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(send_from), Some(relay)) = (&unsubscribe.send_from, &unsubscribe.relay) else {
+        return Err("Pass --send-from and --relay to send mailto unsubscribes".into());
+    };
+    if unsubscribe.username.is_none() && !Path::new("credentials.json").exists() {
+        return Err(format!(
+            "No stored credentials for relay {}, pass --username and --password",
+            relay
+        )
+        .into());
+    }
+
+    let (address, subject, body) = parse_mailto(mailto);
+    let send = Send {
+        from: Some(send_from.clone()),
+        to: Some(vec![address]),
+        cc: None,
+        bcc: None,
+        subject: Some(subject.unwrap_or("unsubscribe".to_string())),
+        description: Some(body.unwrap_or("unsubscribe".to_string())),
+        attachment: None,
+        username: unsubscribe.username.clone(),
+        password: unsubscribe.password.clone(),
+        relay: relay.clone(),
+        json_file: None,
+    };
+    mail::send_message(send, None).await
+}
+
+/// Groups the messages matched by the filter by their List-Id, or by sender when they have none,
+/// keeping the unsubscribe headers of the first message of each group that has them
+async fn collect_lists(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    unsubscribe: &Unsubscribe,
+) -> BTreeMap<String, MailingList> {
+    let msg_id_bts: Arc<tokio_mutex<BTreeSet<Option<String>>>> =
+        Arc::new(tokio_mutex::new(BTreeSet::new()));
+    mail::get_msg_ids_from_messages(
+        hub,
+        &[],
+        Some(unsubscribe.filter.clone()),
+        msg_id_bts.clone(),
+    )
+    .await;
+    let msg_ids: Vec<String> = msg_id_bts.lock().await.iter().flatten().cloned().collect();
+
+    let mut lists: BTreeMap<String, MailingList> = BTreeMap::new();
+    for msg_id in msg_ids {
+        let msg = match mail::get_message_metadata(
+            hub,
+            &msg_id,
+            &[
+                "From",
+                "List-Id",
+                "List-Unsubscribe",
+                "List-Unsubscribe-Post",
+            ],
+        )
+        .await
+        {
+            Ok(msg) => msg,
+            Err(e) => {
+                println!(
+                    "Could not read headers of message with id {}.\nError Received: {}",
+                    msg_id, e
+                );
+                continue;
+            }
+        };
+
+        let name = match mail::get_header(&msg, "List-Id") {
+            Some(list_id) => list_id.trim().to_lowercase(),
+            None => mail::sender_address(&mail::get_header(&msg, "From").unwrap_or_default()),
+        };
+        let list = lists.entry(name.clone()).or_insert(MailingList {
+            name,
+            messages: 0,
+            unsubscribe: None,
+            one_click: false,
+        });
+        list.messages += 1;
+
+        if list.unsubscribe.is_none() {
+            list.unsubscribe = mail::get_header(&msg, "List-Unsubscribe");
+            list.one_click = mail::get_header(&msg, "List-Unsubscribe-Post")
+                .is_some_and(|post| post.contains("List-Unsubscribe=One-Click"));
+        }
+    }
+    lists
+}
+
+/// Unsubscribes from every mailing list (or sender) in the messages matched by the filter, once per
+/// list, through a one-click POST where the list supports it and an unsubscribe email otherwise.
+/// Prints a report of what was attempted and what succeeded. On a dry run nothing is sent
+pub async fn unsubscribe(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    unsubscribe: &Unsubscribe,
+    dry_run: bool,
+) {
+    let lists = collect_lists(hub, unsubscribe).await;
+    if lists.is_empty() {
+        println!("No messages matched, nothing to unsubscribe from");
+        return;
+    }
+
+    let mut attempts: Vec<Attempt> = Vec::with_capacity(lists.len());
+    for list in lists.values() {
+        let list_label = format!("{} ({} messages)", list.name, list.messages);
+        let (method, result) = match pick_method(list) {
+            None => (
+                "none".to_string(),
+                Err("No List-Unsubscribe header".to_string()),
+            ),
+            Some(Method::Manual(url)) => (
+                "manual".to_string(),
+                Err(format!("Only a web page to visit: {}", url)),
+            ),
+            Some(Method::OneClick(url)) => {
+                let result = if dry_run {
+                    Ok("would POST (dry run)".to_string())
+                } else {
+                    one_click_post(&url).await.map_err(|e| e.to_string())
+                };
+                (format!("one-click {}", url), result)
+            }
+            Some(Method::Mailto(mailto)) => {
+                let result = if dry_run {
+                    Ok("would send (dry run)".to_string())
+                } else {
+                    send_mailto(unsubscribe, &mailto)
+                        .await
+                        .map(|()| "sent".to_string())
+                        .map_err(|e| e.to_string())
+                };
+                (mailto, result)
+            }
+        };
+
+        let succeeded = result.is_ok();
+        attempts.push(Attempt {
+            list: list_label,
+            method,
+            result: result.unwrap_or_else(|e| e),
+            succeeded,
+        });
+    }
+
+    println!("\nUnsubscribe report:");
+    for attempt in &attempts {
+        println!(
+            "[{}] {}\n    via {}\n    {}",
+            if attempt.succeeded { "ok" } else { "failed" },
+            attempt.list,
+            attempt.method,
+            attempt.result
+        );
+    }
+    let succeeded = attempts.iter().filter(|attempt| attempt.succeeded).count();
+    if dry_run {
+        println!(
+            "\nWould try to unsubscribe from {} of {} lists",
+            succeeded,
+            attempts.len()
+        );
+    } else {
+        println!(
+            "\nUnsubscribed from {} of {} lists",
+            succeeded,
+            attempts.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(unsubscribe: &str, one_click: bool) -> MailingList {
+        MailingList {
+            name: "list".to_string(),
+            messages: 1,
+            unsubscribe: Some(unsubscribe.to_string()),
+            one_click,
+        }
+    }
+
+    #[test]
+    fn splits_unsubscribe_headers() {
+        assert_eq!(
+            unsubscribe_uris(
+                "<mailto:leave@example.com?subject=stop>, < https://example.com/u?id=1 >"
+            ),
+            vec![
+                "mailto:leave@example.com?subject=stop",
+                "https://example.com/u?id=1"
+            ]
+        );
+        assert!(unsubscribe_uris("mailto:leave@example.com").is_empty());
+        assert!(unsubscribe_uris("").is_empty());
+    }
+
+    #[test]
+    fn prefers_one_click_then_mailto_then_manual() {
+        let header = "<mailto:leave@example.com>, <https://example.com/u>";
+        assert!(matches!(
+            pick_method(&list(header, true)),
+            Some(Method::OneClick(uri)) if uri == "https://example.com/u"
+        ));
+        assert!(matches!(
+            pick_method(&list(header, false)),
+            Some(Method::Mailto(uri)) if uri == "mailto:leave@example.com"
+        ));
+        assert!(matches!(
+            pick_method(&list("<http://example.com/u>", true)),
+            Some(Method::Manual(uri)) if uri == "http://example.com/u"
+        ));
+        assert!(pick_method(&list("<ftp://example.com/u>", false)).is_none());
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("Unsubscribe%20me%21"), "Unsubscribe me!");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn parses_mailto_uris() {
+        assert_eq!(
+            parse_mailto("mailto:leave%40example.com?Subject=Unsubscribe%20please&body=id%3D42"),
+            (
+                "leave@example.com".to_string(),
+                Some("Unsubscribe please".to_string()),
+                Some("id=42".to_string())
+            )
+        );
+        assert_eq!(
+            parse_mailto("mailto:leave@example.com"),
+            ("leave@example.com".to_string(), None, None)
+        );
+    }
+}
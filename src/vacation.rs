@@ -0,0 +1,227 @@
+extern crate google_gmail1 as gmail1;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use gmail1::api::VacationSettings;
+use gmail1::hyper::client::HttpConnector;
+use gmail1::hyper_rustls::HttpsConnector;
+use gmail1::Gmail;
+use std::fs;
+
+use crate::cmd_args::VacationEnable;
+
+/// Parses a time given as RFC 3339, or as local "YYYY-MM-DD HH:MM" or YYYY-MM-DD (midnight),
+/// into epoch milliseconds
+pub fn parse_time(time: &str) -> Result<i64, String> {
+    let time = time.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(date_time.timestamp_millis());
+    }
+
+    let naive = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| {
+            format!(
+                "Time {} isn't RFC 3339, \"YYYY-MM-DD HH:MM\" or YYYY-MM-DD",
+                time
+            )
+        })?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|date_time| date_time.timestamp_millis())
+        .ok_or(format!(
+            "Time {} doesn't exist in the local time zone",
+            time
+        ))
+}
+
+/// Formats epoch milliseconds as local time
+fn format_time(millis: i64) -> String {
+    Local
+        .timestamp_millis_opt(millis)
+        .single()
+        .map(|date_time| date_time.format("%Y-%m-%d %H:%M %Z").to_string())
+        .unwrap_or(millis.to_string())
+}
+
+/// Fetches the vacation auto-reply settings
+async fn get_vacation(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<VacationSettings, Box<dyn std::error::Error>> {
+    let (_res, vacation) = hub
+        .users()
+        .settings_get_vacation("me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await
+        .map_err(|e| format!("Could not read vacation settings.\nError Received: {}", e))?;
+    Ok(vacation)
+}
+
+/// Saves the vacation auto-reply settings
+async fn update_vacation(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    vacation: VacationSettings,
+) -> Result<VacationSettings, Box<dyn std::error::Error>> {
+    let (_res, vacation) = hub
+        .users()
+        .settings_update_vacation(vacation, "me")
+        .add_scope("https://mail.google.com/")
+        .doit()
+        .await
+        .map_err(|e| format!("Could not update vacation settings.\nError Received: {}", e))?;
+    Ok(vacation)
+}
+
+/// Prints vacation auto-reply settings
+fn print_vacation(vacation: &VacationSettings) {
+    let enabled = vacation.enable_auto_reply.unwrap_or(false);
+    println!("Auto-reply: {}", if enabled { "on" } else { "off" });
+    if let Some(subject) = &vacation.response_subject {
+        println!("Subject: {}", subject);
+    }
+    match (
+        &vacation.response_body_html,
+        &vacation.response_body_plain_text,
+    ) {
+        (Some(html), _) => println!("Body (HTML):\n{}", html),
+        (None, Some(plain_text)) => println!("Body:\n{}", plain_text),
+        (None, None) => {}
+    }
+    if let Some(start_time) = vacation.start_time {
+        println!("Starts: {}", format_time(start_time));
+    }
+    if let Some(end_time) = vacation.end_time {
+        println!("Ends: {}", format_time(end_time));
+    }
+    if vacation.restrict_to_contacts == Some(true) {
+        println!("Only replies to contacts");
+    }
+    if vacation.restrict_to_domain == Some(true) {
+        println!("Only replies within the domain");
+    }
+}
+
+/// Prints the vacation auto-reply settings
+pub async fn show_vacation(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    print_vacation(&get_vacation(hub).await?);
+    Ok(())
+}
+
+/// Turns the vacation auto-reply on with the given subject, body, times and restrictions, replacing
+/// the previous ones. On a dry run the settings are only printed
+pub async fn enable_vacation(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    enable: &VacationEnable,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let (Some(start), Some(end)) = (enable.start, enable.end) {
+        if start >= end {
+            return Err("The start time has to be before the end time".into());
+        }
+    }
+
+    let body =
+        match &enable.body_file {
+            Some(body_file) => Some(fs::read_to_string(body_file).map_err(|e| {
+                format!("Unable to read file {}.\nError Received: {}", body_file, e)
+            })?),
+            None => enable.body.clone(),
+        };
+    let (response_body_html, response_body_plain_text) = if enable.html {
+        (body, None)
+    } else {
+        (None, body)
+    };
+
+    let vacation = VacationSettings {
+        enable_auto_reply: Some(true),
+        response_subject: enable.subject.clone(),
+        response_body_html,
+        response_body_plain_text,
+        start_time: enable.start,
+        end_time: enable.end,
+        restrict_to_contacts: Some(enable.contacts_only),
+        restrict_to_domain: Some(enable.domain_only),
+    };
+
+    if dry_run {
+        println!("Dry run: would set vacation settings to");
+        print_vacation(&vacation);
+        return Ok(());
+    }
+    let vacation = update_vacation(hub, vacation).await?;
+    println!("Vacation auto-reply enabled");
+    print_vacation(&vacation);
+    Ok(())
+}
+
+/// Turns the vacation auto-reply off, keeping the rest of its settings. On a dry run nothing is changed
+pub async fn disable_vacation(
+    hub: &Gmail<HttpsConnector<HttpConnector>>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut vacation = get_vacation(hub).await?;
+    if vacation.enable_auto_reply != Some(true) {
+        println!("Vacation auto-reply is already off");
+        return Ok(());
+    }
+    if dry_run {
+        println!("Dry run: would turn the vacation auto-reply off");
+        return Ok(());
+    }
+
+    vacation.enable_auto_reply = Some(false);
+    update_vacation(hub, vacation).await?;
+    println!("Vacation auto-reply disabled");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_millis(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .single()
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn parses_rfc_3339() {
+        assert_eq!(parse_time("2024-07-01T09:00:00Z"), Ok(1_719_824_400_000));
+        assert_eq!(
+            parse_time(" 2024-07-01T11:00:00+02:00 "),
+            Ok(1_719_824_400_000)
+        );
+    }
+
+    #[test]
+    fn parses_local_times_and_dates() {
+        assert_eq!(
+            parse_time("2024-07-01 09:30"),
+            Ok(local_millis(2024, 7, 1, 9, 30))
+        );
+        assert_eq!(parse_time("2024-07-01"), Ok(local_millis(2024, 7, 1, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        for time in [
+            "",
+            "tomorrow",
+            "07/01/2024",
+            "2024-13-01",
+            "2024-07-01 25:00",
+        ] {
+            assert!(parse_time(time).is_err(), "{}", time);
+        }
+    }
+}